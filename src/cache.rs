@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use serde_json;
+use evaluator::{self, Evaluator, PlaceholderAction, RefDef, Worker};
+use page::{Page, Slug};
+use directives::versions::VersionNote;
+use numbering::Counter;
+use toctree::EntrySource;
+
+const CACHE_FILE_NAME: &str = "pages.json";
+
+/// Identifies a version of a file without reading it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    len: u64,
+    modified: (u64, u32),
+}
+
+impl FileStamp {
+    pub fn new(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(FileStamp {
+            len: metadata.len(),
+            modified: (modified.as_secs(), modified.subsec_nanos()),
+        })
+    }
+}
+
+/// A compiled page, along with everything it contributed to the evaluator.
+#[derive(Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub page: Page,
+    pub refdefs: Vec<(String, RefDef)>,
+    pub toctree_entries: Vec<(Slug, Option<String>, EntrySource)>,
+    pub version_notes: Vec<VersionNote>,
+
+    /// How many figures and tables the page numbered
+    pub counters: Vec<(Counter, usize)>,
+    pub dependencies: Vec<(PathBuf, FileStamp)>,
+
+    /// A hash of every input to the rendered output of this page
+    pub output_hash: Option<u64>,
+
+    /// Links that the placeholders in page.body refer to. Only populated on disk.
+    links: Vec<(PlaceholderAction, String)>,
+
+    /// Pages that logged errors or warnings while compiling should be recompiled, so
+    /// that they are reported again.
+    #[serde(skip)]
+    pub cacheable: bool,
+}
+
+impl CacheEntry {
+    pub fn new(worker: &Worker, page: Page) -> Self {
+        let dependencies = worker
+            .get_dependencies()
+            .into_iter()
+            .filter_map(|path| FileStamp::new(&path).map(|stamp| (path, stamp)))
+            .collect();
+
+        CacheEntry {
+            page,
+            refdefs: worker.page_refdefs.clone(),
+            toctree_entries: worker.page_toctree_entries.clone(),
+            version_notes: worker.page_version_notes.clone(),
+            counters: worker
+                .page_counters
+                .iter()
+                .map(|(&counter, &n)| (counter, n))
+                .collect(),
+            dependencies,
+            output_hash: None,
+            links: vec![],
+            cacheable: worker.get_error_count() == 0 && worker.get_warning_count() == 0,
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        !self.dependencies.is_empty()
+            && self.dependencies
+                .iter()
+                .all(|(path, stamp)| FileStamp::new(path).as_ref() == Some(stamp))
+    }
+
+    /// Register this entry's references, toctree entries, version notes, and links
+    /// with the evaluator as if the page had just been compiled.
+    fn restore(&mut self, evaluator: &Evaluator, prefix: &str) -> Result<(), ()> {
+        self.page.body = evaluator.restore_links(&self.page.body, prefix, &self.links)?;
+        self.links.clear();
+
+        let mut refdefs = evaluator.refdefs.write().unwrap();
+        for (refid, refdef) in &self.refdefs {
+            refdefs.insert(refid.to_owned(), refdef.clone());
+        }
+
+        let mut toctree = evaluator.toctree.write().unwrap();
+        for (slug, title, source) in &self.toctree_entries {
+            toctree.add(
+                &self.page.slug,
+                slug.to_owned(),
+                title.to_owned(),
+                source.to_owned(),
+            );
+        }
+
+        evaluator
+            .version_notes
+            .write()
+            .unwrap()
+            .extend(self.version_notes.iter().cloned());
+
+        self.cacheable = true;
+        Ok(())
+    }
+}
+
+/// The set of compiled pages stored in the .rocket-cache directory.
+#[derive(Serialize, Deserialize)]
+pub struct Cache {
+    /// Identifies the configuration and Rocket version that produced this cache
+    key: u64,
+    placeholder_prefix: String,
+    entries: Vec<CacheEntry>,
+}
+
+impl Cache {
+    pub fn new(key: u64) -> Self {
+        Cache {
+            key,
+            placeholder_prefix: evaluator::random_placeholder_prefix(),
+            entries: vec![],
+        }
+    }
+
+    /// Load the cache from the given directory. Returns an empty cache if none exists,
+    /// or if it was created with a different key.
+    pub fn load(dir: &Path, key: u64) -> Self {
+        let path = dir.join(CACHE_FILE_NAME);
+        let mut data = String::new();
+        match File::open(&path) {
+            Ok(mut f) => if f.read_to_string(&mut data).is_err() {
+                return Self::new(key);
+            },
+            Err(_) => return Self::new(key),
+        }
+
+        match serde_json::from_str::<Cache>(&data) {
+            Ok(ref cache) if cache.key != key => {
+                debug!("Configuration changed; discarding build cache");
+                Self::new(key)
+            }
+            Ok(cache) => cache,
+            Err(err) => {
+                warn!("Failed to read build cache: {}", err);
+                Self::new(key)
+            }
+        }
+    }
+
+    /// Remove and return every up-to-date entry whose source is one of the given paths,
+    /// restoring each one into the evaluator.
+    pub fn take_fresh(&mut self, paths: &[PathBuf], evaluator: &Evaluator) -> Vec<CacheEntry> {
+        let mut entries: HashMap<PathBuf, CacheEntry> = self.entries
+            .drain(..)
+            .map(|entry| (entry.page.source_path.to_owned(), entry))
+            .collect();
+
+        let mut result = vec![];
+        for path in paths {
+            let mut entry = match entries.remove(path) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            if !entry.is_fresh() {
+                continue;
+            }
+
+            if entry.restore(evaluator, &self.placeholder_prefix).is_err() {
+                warn!("Corrupt cache entry for {}", path.to_string_lossy());
+                continue;
+            }
+
+            result.push(entry);
+        }
+
+        result
+    }
+
+    pub fn push(&mut self, mut entry: CacheEntry, evaluator: &Evaluator) {
+        if !entry.cacheable {
+            return;
+        }
+
+        let (body, links) = evaluator.extract_links(&entry.page.body, &self.placeholder_prefix);
+        entry.page.body = body;
+        entry.links = links;
+        self.entries.push(entry);
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let data = serde_json::to_string(self)?;
+
+        // Write to a temporary file first, so that an interrupted build doesn't leave
+        // behind a truncated cache.
+        let path = dir.join(CACHE_FILE_NAME);
+        let tmp_path = path.with_extension("json.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data.as_bytes())?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lex::Span;
+    use parse::Node;
+
+    #[test]
+    fn test_restore() {
+        let evaluator = Evaluator::new();
+        let entry = {
+            let mut worker = Worker::new(&evaluator);
+            worker.set_slug(Slug::new("index".to_owned()));
+            let title = worker.get_placeholder("ref-a".to_owned(), PlaceholderAction::Title);
+            let path = worker.get_placeholder("ref-b".to_owned(), PlaceholderAction::Path);
+            let refdef = RefDef::new("A", worker.get_slug());
+            worker.insert_refdef(&Node::new_string("", 0, Span::unknown()), "ref-a".to_owned(), refdef);
+
+            let page = Page {
+                source_path: PathBuf::from("index.rocket"),
+                slug: worker.get_slug().to_owned(),
+                body: format!(r#"<a href="{}">{}</a>"#, path, title),
+                theme_config: serde_json::map::Map::new(),
+                assets: vec![],
+            };
+            CacheEntry::new(&worker, page)
+        };
+
+        let mut cache = Cache::new(0);
+        cache.push(entry, &evaluator);
+        let mut entry = cache.entries.pop().unwrap();
+        assert_eq!(entry.links.len(), 2);
+
+        let new_evaluator = Evaluator::new();
+        new_evaluator.refdefs.write().unwrap().insert(
+            "ref-b".to_owned(),
+            RefDef::new("B", &Slug::new("b/c".to_owned())),
+        );
+        entry
+            .restore(&new_evaluator, &cache.placeholder_prefix)
+            .unwrap();
+
+        assert_eq!(
+            new_evaluator.substitute(&entry.page),
+            r#"<a href="b/c">A</a>"#.to_owned()
+        );
+    }
+}
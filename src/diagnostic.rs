@@ -0,0 +1,426 @@
+use std::{cmp, fmt};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use log;
+use lex::Span;
+use parse::{FileID, Node};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnknownName,
+    MissingArgument,
+    TooManyArguments,
+    InvalidArgument,
+    UnknownLanguage,
+    ArgumentMismatch,
+    InvalidRegex,
+    IncludeFailed,
+    MissingAsset,
+    InvalidHeadingLevel,
+    UnknownReference,
+    SyntaxError,
+    DuplicateReference,
+    MissingPage,
+    UnreachablePage,
+    DuplicateEntry,
+    TocTreeCycle,
+    UnreleasedVersion,
+    OutdatedDeprecation,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = match *self {
+            ErrorCode::UnknownName => "E001",
+            ErrorCode::MissingArgument => "E002",
+            ErrorCode::TooManyArguments => "E003",
+            ErrorCode::InvalidArgument => "E004",
+            ErrorCode::UnknownLanguage => "E005",
+            ErrorCode::ArgumentMismatch => "E006",
+            ErrorCode::InvalidRegex => "E007",
+            ErrorCode::IncludeFailed => "E008",
+            ErrorCode::MissingAsset => "E009",
+            ErrorCode::InvalidHeadingLevel => "E010",
+            ErrorCode::UnknownReference => "E011",
+            ErrorCode::SyntaxError => "E012",
+            ErrorCode::DuplicateReference => "E013",
+            ErrorCode::MissingPage => "E014",
+            ErrorCode::UnreachablePage => "E015",
+            ErrorCode::DuplicateEntry => "E016",
+            ErrorCode::TocTreeCycle => "E017",
+            ErrorCode::UnreleasedVersion => "E018",
+            ErrorCode::OutdatedDeprecation => "E019",
+        };
+
+        write!(f, "{}", code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// The parsed file that this location refers to. File IDs are only meaningful to
+    /// the parser that assigned them, so locations found outside of a worker have none,
+    /// and the path must be given when reporting them.
+    pub file_id: Option<FileID>,
+    pub span: Span,
+}
+
+impl<'a> From<&'a Node> for Location {
+    fn from(node: &'a Node) -> Self {
+        Location {
+            file_id: Some(node.file_id),
+            span: node.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub code: ErrorCode,
+    pub message: String,
+    pub location: Option<Location>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(level: Level, code: ErrorCode, message: S) -> Self {
+        Diagnostic {
+            level,
+            code,
+            message: message.into(),
+            location: None,
+            notes: vec![],
+        }
+    }
+
+    pub fn error<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Self::new(Level::Error, code, message)
+    }
+
+    pub fn warning<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Self::new(Level::Warning, code, message)
+    }
+
+    pub fn missing_argument(name: &str) -> Self {
+        Self::error(ErrorCode::MissingArgument, format!("missing argument <{}>", name))
+    }
+
+    pub fn too_many_arguments(expected: usize) -> Self {
+        let plural = if expected == 1 { "" } else { "s" };
+        Self::error(
+            ErrorCode::TooManyArguments,
+            format!("too many arguments: expected at most {} argument{}", expected, plural),
+        )
+    }
+
+    /// Point this diagnostic at the given node.
+    pub fn at(self, node: &Node) -> Self {
+        self.at_span(node.file_id, node.span)
+    }
+
+    /// Point this diagnostic at a span of the given file.
+    pub fn at_span(mut self, file_id: FileID, span: Span) -> Self {
+        self.location = Some(Location {
+            file_id: Some(file_id),
+            span,
+        });
+        self
+    }
+
+    /// Point this diagnostic at a span of whichever file it is reported against.
+    pub fn at_path_span(mut self, span: Span) -> Self {
+        self.location = Some(Location {
+            file_id: None,
+            span,
+        });
+        self
+    }
+
+    /// Point this diagnostic at the given node if it doesn't already have a location.
+    pub fn or_at(self, node: &Node) -> Self {
+        if self.location.is_some() {
+            self
+        } else {
+            self.at(node)
+        }
+    }
+
+    pub fn with_note<S: Into<String>>(mut self, note: S) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Render this diagnostic in the style of rustc, with a snippet of the source
+    /// file at the given path.
+    pub fn render(&self, path: Option<&Path>) -> String {
+        let mut source = String::new();
+        let source = match path.map(File::open) {
+            Some(Ok(mut f)) => match f.read_to_string(&mut source) {
+                Ok(_) => Some(source.as_ref()),
+                Err(_) => None,
+            },
+            _ => None,
+        };
+
+        let path = path.map(|p| p.to_string_lossy().into_owned());
+        self.render_source(path.as_ref().map(|p| p.as_ref()), source)
+    }
+
+    fn render_source(&self, path: Option<&str>, source: Option<&str>) -> String {
+        let mut result = format!("{}[{}]: {}", self.level, self.code, self.message);
+
+        let span = match self.location {
+            Some(ref location) if location.span.lineno() >= 0 => Some(location.span),
+            _ => None,
+        };
+
+        let gutter = match span {
+            Some(span) => " ".repeat((span.lineno() + 1).to_string().len()),
+            None => String::new(),
+        };
+
+        if let Some(path) = path {
+            match span {
+                Some(span) => result.push_str(&format!(
+                    "\n{}--> {}:{}:{}",
+                    gutter,
+                    path,
+                    span.lineno() + 1,
+                    span.start.column + 1
+                )),
+                None => result.push_str(&format!("\n--> {}", path)),
+            }
+        }
+
+        let line_text = match (source, span) {
+            (Some(source), Some(span)) => source.lines().nth(span.lineno() as usize),
+            _ => None,
+        };
+
+        if let (Some(text), Some(span)) = (line_text, span) {
+            // Underline the span, or the remainder of the line if the span continues
+            // onto later lines
+            let start = span.start.column as usize;
+            let end = if span.end.lineno == span.start.lineno {
+                span.end.column as usize
+            } else {
+                text.chars().count()
+            };
+
+            result.push_str(&format!(
+                "\n{} |\n{} | {}\n{} | {}{}",
+                gutter,
+                span.lineno() + 1,
+                text,
+                gutter,
+                " ".repeat(start),
+                "^".repeat(cmp::max(1, end.saturating_sub(start)))
+            ));
+        }
+
+        for note in &self.notes {
+            result.push_str(&format!("\n{} = note: {}", gutter, note));
+        }
+
+        result
+    }
+}
+
+/// The number of errors and warnings reported during a build.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{} error{} and {} warning{}",
+            self.errors,
+            plural(self.errors),
+            self.warnings,
+            plural(self.warnings)
+        )
+    }
+}
+
+/// Logs diagnostics and counts them across every thread of a build.
+#[derive(Default)]
+pub struct Reporter {
+    warnings_as_errors: bool,
+    n_errors: AtomicUsize,
+    n_warnings: AtomicUsize,
+}
+
+impl Reporter {
+    pub fn new(warnings_as_errors: bool) -> Self {
+        Reporter {
+            warnings_as_errors,
+            ..Default::default()
+        }
+    }
+
+    fn count(&self, level: Level) -> Level {
+        let level = if self.warnings_as_errors {
+            Level::Error
+        } else {
+            level
+        };
+
+        match level {
+            Level::Error => self.n_errors.fetch_add(1, Ordering::SeqCst),
+            Level::Warning => self.n_warnings.fetch_add(1, Ordering::SeqCst),
+        };
+
+        level
+    }
+
+    /// Log a diagnostic concerning the file at path. Returns the level at which it
+    /// was reported, which is always Error in warnings-as-errors mode.
+    pub fn report(&self, diagnostic: &Diagnostic, path: Option<&Path>) -> Level {
+        let level = self.count(diagnostic.level);
+        let rendered = if level == diagnostic.level {
+            diagnostic.render(path)
+        } else {
+            Diagnostic {
+                level,
+                ..diagnostic.clone()
+            }.render(path)
+        };
+
+        match level {
+            Level::Error => log!(log::LogLevel::Error, "{}", rendered),
+            Level::Warning => log!(log::LogLevel::Warn, "{}", rendered),
+        }
+
+        level
+    }
+
+    /// Log and count an error that isn't associated with any source file.
+    pub fn error<S: AsRef<str>>(&self, message: S) {
+        self.count(Level::Error);
+        error!("{}", message.as_ref());
+    }
+
+    pub fn counts(&self) -> Counts {
+        Counts {
+            errors: self.n_errors.load(Ordering::SeqCst),
+            warnings: self.n_warnings.load(Ordering::SeqCst),
+        }
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = cmp::min(substitution, cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Find the candidate closest to target, if any is close enough to plausibly be
+/// what was meant.
+pub fn closest_match<'a, I>(target: &str, candidates: I) -> Option<&'a str>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let threshold = cmp::min(2, target.chars().count() / 2);
+    candidates
+        .filter(|candidate| *candidate != target)
+        .map(|candidate| (edit_distance(target, candidate), candidate))
+        .filter(|&(distance, _)| distance <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lex::Position;
+
+    #[test]
+    fn test_closest_match() {
+        let candidates = ["py", "python", "rust", "rs", "h1", "h2"];
+        assert_eq!(closest_match("pyhton", candidates.iter().cloned()), Some("python"));
+        assert_eq!(closest_match("rutt", candidates.iter().cloned()), Some("rust"));
+        assert_eq!(closest_match("h7", candidates.iter().cloned()), Some("h1"));
+        assert_eq!(closest_match("javascript", candidates.iter().cloned()), None);
+        assert_eq!(closest_match("x", candidates.iter().cloned()), None);
+    }
+
+    #[test]
+    fn test_reporter() {
+        let reporter = Reporter::new(false);
+        let warning = Diagnostic::warning(ErrorCode::InvalidArgument, "foo");
+        assert_eq!(reporter.report(&warning, None), Level::Warning);
+        reporter.error("bar");
+        assert_eq!(reporter.counts(), Counts { errors: 1, warnings: 1 });
+        assert_eq!(reporter.counts().to_string(), "1 error and 1 warning");
+
+        let reporter = Reporter::new(true);
+        assert_eq!(reporter.report(&warning, None), Level::Error);
+        assert_eq!(reporter.counts(), Counts { errors: 1, warnings: 0 });
+        assert_eq!(reporter.counts().to_string(), "1 error and 0 warnings");
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "(:h1 Title)\n\n(:code pyhton =>\n    print('hi')\n";
+        let diagnostic =
+            Diagnostic::error(ErrorCode::UnknownLanguage, "unknown language 'pyhton'")
+                .at(&Node::new_string(
+                    "pyhton",
+                    0,
+                    Span::new(Position::new(20, 2, 7), Position::new(26, 2, 13)),
+                ))
+                .with_note("did you mean 'python'?");
+
+        assert_eq!(
+            diagnostic.render_source(Some("content/index.rocket"), Some(source)),
+            concat!(
+                "error[E005]: unknown language 'pyhton'\n",
+                " --> content/index.rocket:3:8\n",
+                "  |\n",
+                "3 | (:code pyhton =>\n",
+                "  |        ^^^^^^\n",
+                "  = note: did you mean 'python'?"
+            )
+        );
+
+        let diagnostic = Diagnostic::too_many_arguments(1);
+        assert_eq!(
+            diagnostic.render_source(None, None),
+            "error[E003]: too many arguments: expected at most 1 argument"
+        );
+    }
+}
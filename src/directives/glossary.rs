@@ -1,3 +1,4 @@
+use diagnostic::{Diagnostic, ErrorCode};
 use directives::{concat_nodes, escape_string, DirectiveHandler};
 use evaluator::{RefDef, Worker};
 use parse::{Node, NodeValue};
@@ -5,18 +6,23 @@ use parse::{Node, NodeValue};
 pub struct Glossary;
 
 impl DirectiveHandler for Glossary {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut result = String::with_capacity(1024);
         result.push_str(r#"<dl class="glossary">"#);
 
         for node in args {
             let children = match node.value {
-                NodeValue::Owned(_) => return Err(()),
-                NodeValue::Children(ref children) => children,
+                NodeValue::Children(ref children) if !children.is_empty() => children,
+                _ => {
+                    return Err(Diagnostic::error(
+                        ErrorCode::InvalidArgument,
+                        "expected a term and its definition",
+                    ).at(node))
+                }
             };
 
-            let mut iter = children.iter();
-            let term = worker.evaluate(iter.next().ok_or(())?);
+            let term = worker.evaluate(&children[0]);
+            let mut iter = children[1..].iter();
             let ref_id = format!("term-{}", escape_string(&term));
             let body = concat_nodes(&mut iter, worker, " ");
             result.push_str(&format!(r#"<dt id="{}">"#, ref_id));
@@ -26,7 +32,7 @@ impl DirectiveHandler for Glossary {
             result.push_str("</dd>");
 
             let refdef = RefDef::new(&term, worker.get_slug());
-            worker.insert_refdef(ref_id, refdef);
+            worker.insert_refdef(node, ref_id, refdef);
         }
 
         result.push_str("</dl>");
@@ -1,18 +1,22 @@
+use std::collections::HashSet;
 use parse::Node;
 use evaluator::Worker;
+use diagnostic::{Diagnostic, ErrorCode};
 use directives::{consume_string, DirectiveHandler};
 
 pub struct If;
 
 impl DirectiveHandler for If {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let condition = consume_string(&mut iter, worker).ok_or(())?;
-        let if_true = iter.next().ok_or(())?;
+        let condition = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("condition"))?;
+        let if_true = iter.next()
+            .ok_or_else(|| Diagnostic::missing_argument("if-true"))?;
         let if_false = iter.next();
 
         if iter.next().is_some() {
-            return Err(());
+            return Err(Diagnostic::too_many_arguments(3));
         }
 
         if condition.is_empty() {
@@ -29,13 +33,14 @@ impl DirectiveHandler for If {
 pub struct Not;
 
 impl DirectiveHandler for Not {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
-        if args.len() != 1 {
-            return Err(());
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        if args.len() > 1 {
+            return Err(Diagnostic::too_many_arguments(1));
         }
 
         let mut iter = args.iter();
-        let value = consume_string(&mut iter, worker).ok_or(())?;
+        let value = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("value"))?;
 
         if value.is_empty() {
             Ok("true".to_owned())
@@ -48,13 +53,14 @@ impl DirectiveHandler for Not {
 pub struct Equals;
 
 impl DirectiveHandler for Equals {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         if args.len() < 2 {
-            return Err(());
+            return Err(Diagnostic::missing_argument("value"));
         }
 
         let mut iter = args.iter();
-        let initial = consume_string(&mut iter, worker).ok_or(())?;
+        let initial = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("value"))?;
 
         let is_true = iter.all(|node| initial == worker.evaluate(node));
 
@@ -69,7 +75,7 @@ impl DirectiveHandler for Equals {
 pub struct NotEquals;
 
 impl DirectiveHandler for NotEquals {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let equals = Equals;
         let result = equals.handle(worker, args)?;
 
@@ -81,19 +87,156 @@ impl DirectiveHandler for NotEquals {
     }
 }
 
+/// A recursive descent parser for tag expressions such as
+/// "linux and not (arm or mips)". `not` binds tightest, then `and`, then `or`.
+struct TagExpression<'a> {
+    tokens: Vec<&'a str>,
+    position: usize,
+    tags: &'a HashSet<String>,
+}
+
+impl<'a> TagExpression<'a> {
+    fn new(expression: &'a str, tags: &'a HashSet<String>) -> Self {
+        let mut tokens = vec![];
+        let mut start = None;
+        for (i, c) in expression.char_indices() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                if let Some(start) = start.take() {
+                    tokens.push(&expression[start..i]);
+                }
+
+                if !c.is_whitespace() {
+                    tokens.push(&expression[i..i + 1]);
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+
+        if let Some(start) = start {
+            tokens.push(&expression[start..]);
+        }
+
+        TagExpression {
+            tokens,
+            position: 0,
+            tags,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn evaluate(&mut self) -> Result<bool, String> {
+        let value = self.or_expression()?;
+        match self.peek() {
+            None => Ok(value),
+            Some(token) => Err(format!("unexpected '{}'", token)),
+        }
+    }
+
+    fn or_expression(&mut self) -> Result<bool, String> {
+        let mut value = self.and_expression()?;
+        while self.peek() == Some("or") {
+            self.next();
+            value = self.and_expression()? || value;
+        }
+
+        Ok(value)
+    }
+
+    fn and_expression(&mut self) -> Result<bool, String> {
+        let mut value = self.not_expression()?;
+        while self.peek() == Some("and") {
+            self.next();
+            value = self.not_expression()? && value;
+        }
+
+        Ok(value)
+    }
+
+    fn not_expression(&mut self) -> Result<bool, String> {
+        match self.next() {
+            Some("not") => Ok(!self.not_expression()?),
+            Some("(") => {
+                let value = self.or_expression()?;
+                match self.next() {
+                    Some(")") => Ok(value),
+                    _ => Err("unmatched '('".to_owned()),
+                }
+            }
+            Some(token) if token == ")" || token == "and" || token == "or" => {
+                Err(format!("expected a tag but found '{}'", token))
+            }
+            Some(tag) => Ok(self.tags.contains(tag)),
+            None => Err("expected a tag".to_owned()),
+        }
+    }
+}
+
+/// Whether a tag expression such as "linux and not enterprise" holds for the given
+/// set of tags.
+pub fn match_tags(expression: &str, tags: &HashSet<String>) -> Result<bool, String> {
+    TagExpression::new(expression, tags).evaluate()
+}
+
+/// Include content only when the build's tags satisfy an expression. Without any
+/// content, the whole page is excluded unless the expression holds.
+pub struct Only;
+
+impl DirectiveHandler for Only {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        let mut iter = args.iter();
+        let expression = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("expression"))?;
+        let body = iter.next();
+
+        if iter.next().is_some() {
+            return Err(Diagnostic::too_many_arguments(2));
+        }
+
+        let matches = match_tags(&expression, worker.tags()).map_err(|msg| {
+            Diagnostic::error(
+                ErrorCode::InvalidArgument,
+                format!("invalid tag expression: {}", msg),
+            ).at(&args[0])
+        })?;
+
+        match body {
+            Some(body) if matches => Ok(worker.evaluate(body)),
+            Some(_) => Ok("".to_owned()),
+            None => {
+                if !matches {
+                    worker.exclude_page();
+                }
+                Ok("".to_owned())
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use directives::*;
     use evaluator::Evaluator;
+    use lex::Span;
+    use page::Slug;
 
 
     fn node_string(s: &str) -> Node {
-        Node::new_string(s, 0, -1)
+        Node::new_string(s, 0, Span::unknown())
     }
 
     fn node_children(nodes: Vec<Node>) -> Node {
-        Node::new_children(nodes, 0, -1)
+        Node::new_children(nodes, 0, Span::unknown())
     }
 
     #[test]
@@ -252,4 +395,55 @@ mod tests {
             Ok("true".to_owned())
         );
     }
+
+    #[test]
+    fn test_match_tags() {
+        let tags: HashSet<_> = ["linux", "enterprise"].iter().map(|t| t.to_string()).collect();
+        let matches = |expression| match_tags(expression, &tags);
+
+        assert_eq!(matches("linux"), Ok(true));
+        assert_eq!(matches("windows"), Ok(false));
+        assert_eq!(matches("not windows and linux"), Ok(true));
+        assert_eq!(matches("windows or linux and enterprise"), Ok(true));
+        assert_eq!(matches("(windows or linux) and not enterprise"), Ok(false));
+        assert_eq!(matches("not not linux"), Ok(true));
+
+        assert_eq!(matches(""), Err("expected a tag".to_owned()));
+        assert_eq!(matches("linux and"), Err("expected a tag".to_owned()));
+        assert_eq!(matches("(linux"), Err("unmatched '('".to_owned()));
+        assert_eq!(matches("linux)"), Err("unexpected ')'".to_owned()));
+        assert_eq!(matches("linux mac"), Err("unexpected 'mac'".to_owned()));
+        assert_eq!(matches("or linux"), Err("expected a tag but found 'or'".to_owned()));
+    }
+
+    #[test]
+    fn test_only() {
+        let mut evaluator = Evaluator::new();
+        evaluator.tags.insert("linux".to_owned());
+        let mut worker = Worker::new(&evaluator);
+        worker.set_slug(Slug::new("index".to_owned()));
+        let handler = Only;
+
+        assert!(handler.handle(&mut worker, &[]).is_err());
+        assert_eq!(
+            handler.handle(&mut worker, &[node_string("linux"), node_string("yes")]),
+            Ok("yes".to_owned())
+        );
+        assert_eq!(
+            handler.handle(&mut worker, &[node_string("not linux"), node_string("no")]),
+            Ok("".to_owned())
+        );
+        assert_eq!(
+            handler
+                .handle(&mut worker, &[node_string("linux and"), node_string("")])
+                .unwrap_err()
+                .code,
+            ErrorCode::InvalidArgument
+        );
+
+        assert!(handler.handle(&mut worker, &[node_string("linux")]).is_ok());
+        assert!(!worker.is_excluded());
+        assert!(handler.handle(&mut worker, &[node_string("windows")]).is_ok());
+        assert!(worker.is_excluded());
+    }
 }
@@ -1,15 +1,22 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::collections::hash_map::Entry;
+use std::fs;
 use std::sync::Arc;
-use std::{cmp, iter, mem, slice, str};
+use std::{cmp, mem, slice, str};
 use regex::{Captures, Regex};
 use serde_json;
+use diagnostic::{Diagnostic, ErrorCode, Level};
 use parse::{Node, NodeValue};
 use page::Slug;
 use evaluator::{PlaceholderAction, RefDef, StoredValue, Worker};
+use highlighter::LineOptions;
+use numbering::Counter;
 
 pub mod logic;
 pub mod glossary;
+pub mod tabs;
+pub mod versions;
 
 fn consume_string(iter: &mut slice::Iter<Node>, worker: &mut Worker) -> Option<String> {
     match iter.next() {
@@ -51,81 +58,360 @@ pub fn concat_nodes(
 }
 
 pub trait DirectiveHandler {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()>;
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic>;
 }
 
 pub struct Dummy;
 
 impl DirectiveHandler for Dummy {
     #[allow(unused_variables)]
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         Ok("".to_owned())
     }
 }
 
+/// Split an option written as name=value, or just name.
+fn split_option(option: &str) -> (&str, Option<&str>) {
+    let mut parts = option.splitn(2, '=');
+    let name = parts.next().unwrap_or("");
+    (name, parts.next())
+}
+
+/// Parse a list of line numbers and ranges, such as "3-5,9".
+fn parse_line_ranges(ranges: &str) -> Result<HashSet<usize>, String> {
+    let parse_line = |line: &str| match line.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid line number '{}'", line.trim())),
+    };
+
+    let mut lines = HashSet::new();
+    for range in ranges.split(',') {
+        let mut bounds = range.splitn(2, '-');
+        let start = parse_line(bounds.next().unwrap_or(""))?;
+        let end = match bounds.next() {
+            Some(end) => parse_line(end)?,
+            None => start,
+        };
+
+        if end < start {
+            return Err(format!("line range '{}' ends before it starts", range.trim()));
+        }
+
+        lines.extend(start..end + 1);
+    }
+
+    Ok(lines)
+}
+
+fn unknown_option(node: &Node, name: &str, expected: &str) -> Diagnostic {
+    Diagnostic::error(
+        ErrorCode::InvalidArgument,
+        format!("unknown option '{}'", name),
+    ).at(node)
+        .with_note(format!("expected one of {}", expected))
+}
+
+/// How to present a highlighted block of code.
+#[derive(Default)]
+struct CodeBlockOptions {
+    lines: LineOptions,
+    caption: Option<String>,
+    filename: Option<String>,
+
+    /// An anchor id, which is also registered as a reference
+    id: Option<(String, Node)>,
+}
+
+impl CodeBlockOptions {
+    const OPTIONS: &'static [&'static str] =
+        &["linenos", "lineno-start", "emphasize", "caption", "filename", "id"];
+
+    /// Whether a node is written as one of these options, rather than as code.
+    fn is_option(node: &Node) -> bool {
+        match node.value {
+            NodeValue::Owned(ref s) => Self::OPTIONS.contains(&split_option(s).0),
+            NodeValue::Children(_) => false,
+        }
+    }
+
+    /// Apply an option node written as name=value, or just name. Returns the option's
+    /// name and value if it isn't a presentation option, so that directives can
+    /// accept options of their own.
+    fn apply(
+        &mut self,
+        worker: &mut Worker,
+        node: &Node,
+    ) -> Result<Option<(String, Option<String>)>, Diagnostic> {
+        let option = worker.evaluate(node);
+        let (name, value) = split_option(&option);
+        let invalid =
+            |message: String| Diagnostic::error(ErrorCode::InvalidArgument, message).at(node);
+        let required =
+            || value.ok_or_else(|| invalid(format!("option '{}' requires a value", name)));
+
+        match name {
+            "linenos" => {
+                self.lines.first_line.get_or_insert(1);
+            }
+            "lineno-start" => {
+                let value = required()?;
+                let first_line = value
+                    .parse::<usize>()
+                    .map_err(|_| invalid(format!("invalid line number '{}'", value)))?;
+                self.lines.first_line = Some(first_line);
+            }
+            "emphasize" => {
+                self.lines.emphasize = parse_line_ranges(required()?).map_err(&invalid)?;
+            }
+            "caption" => self.caption = Some(required()?.to_owned()),
+            "filename" => self.filename = Some(required()?.to_owned()),
+            "id" => self.id = Some((required()?.to_owned(), node.clone())),
+            _ => return Ok(Some((name.to_owned(), value.map(|value| value.to_owned())))),
+        }
+
+        Ok(None)
+    }
+
+    /// Highlight code, given the node naming its language, and wrap it in a figure
+    /// if it has a caption, file name, or id.
+    fn render(
+        &self,
+        worker: &mut Worker,
+        language_node: &Node,
+        language: &str,
+        code: &str,
+    ) -> Result<String, Diagnostic> {
+        let highlighted = worker.highlighter.highlight(language, code, &self.lines);
+        let highlighted = highlighted.map_err(|_| {
+            let diagnostic = Diagnostic::error(
+                ErrorCode::UnknownLanguage,
+                format!("unknown language '{}'", language),
+            ).at(language_node);
+
+            match worker.highlighter.suggest_language(language) {
+                Some(suggestion) => diagnostic.with_note(format!("did you mean '{}'?", suggestion)),
+                None => diagnostic,
+            }
+        })?;
+
+        // An id that another element on the page already has is reported and dropped
+        let id = self.id
+            .as_ref()
+            .filter(|(id, node)| worker.claim_anchor(node, id));
+        if self.caption.is_none() && self.filename.is_none() && id.is_none() {
+            return Ok(highlighted);
+        }
+
+        let mut result = String::from(r#"<figure class="code-block""#);
+        if let Some((id, node)) = id {
+            let title = self.caption
+                .as_ref()
+                .or(self.filename.as_ref())
+                .unwrap_or(id);
+            let refdef = RefDef::new(title, worker.get_slug()).with_fragment(id.as_str());
+            worker.insert_refdef(node, format!("ref-{}", id), refdef);
+            result.push_str(&format!(r#" id="{}""#, escape_string(id)));
+        }
+        result.push('>');
+
+        if self.caption.is_some() || self.filename.is_some() {
+            result.push_str("<figcaption>");
+            if let Some(ref filename) = self.filename {
+                result.push_str(&format!(
+                    r#"<span class="code-filename">{}</span>"#,
+                    escape_string(filename)
+                ));
+            }
+
+            if let Some(ref caption) = self.caption {
+                result.push_str(caption);
+            }
+            result.push_str("</figcaption>");
+        }
+
+        result.push_str(&highlighted);
+        result.push_str("</figure>");
+        Ok(result)
+    }
+}
+
+/// A highlighted block of code. Options may be given between the language and the
+/// code, such as linenos or emphasize=3-5,9.
 pub struct Code;
 
 impl DirectiveHandler for Code {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let language = consume_string(&mut iter, worker).ok_or(())?;
-        let literal = concat_nodes(&mut iter, worker, "");
-        let trimmed = literal.trim();
+        let language = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("language"))?;
+
+        // The last argument is always code, even if it looks like an option
+        let mut options = CodeBlockOptions::default();
+        while iter.len() > 1 && CodeBlockOptions::is_option(&iter.as_slice()[0]) {
+            let node = iter.next().expect("Failed to get option");
+            options.apply(worker, node)?;
+        }
 
-        worker
-            .highlighter
-            .highlight(&language, trimmed)
-            .ok()
-            .ok_or(())
+        let literal = concat_nodes(&mut iter, worker, "");
+        options.render(worker, &args[0], &language, literal.trim())
     }
 }
 
+/// The dot-separated components of a release such as "3.4.0-rc1", ignoring any
+/// pre-release or build suffix.
+pub fn release_components(release: &str) -> Vec<&str> {
+    let core = release.split(&['-', '+'][..]).next();
+    core.unwrap_or("")
+        .split('.')
+        .filter(|component| !component.is_empty())
+        .collect()
+}
+
+/// The project's version, such as "3.4", and its full release string, such as
+/// "3.4.0-rc1".
 pub struct Version {
-    version: Vec<String>,
+    version: String,
+    release: String,
 }
 
 impl Version {
-    pub fn new(version: &str) -> Self {
+    pub fn new(version: &str, release: &str) -> Self {
         Version {
-            version: version.split('.').map(|s| s.to_owned()).collect::<Vec<_>>(),
+            version: version.to_owned(),
+            release: release.to_owned(),
         }
     }
 }
 
 impl DirectiveHandler for Version {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         match args.len() {
-            0 => Ok(self.version.join(".")),
+            0 => Ok(self.version.to_owned()),
             1 => {
                 let arg = worker.evaluate(&args[0]);
-                if arg.is_empty() {
-                    return Ok("".to_owned());
+                let components = release_components(&self.release);
+                let index = match arg.as_str() {
+                    "" => return Ok("".to_owned()),
+                    "release" => return Ok(self.release.to_owned()),
+                    "major" => 0,
+                    "minor" => 1,
+                    "patch" => 2,
+                    _ => {
+                        // As many components as the argument has
+                        let n_components = arg.matches('.').count() + 1;
+                        let n_components = cmp::min(n_components, components.len());
+                        return Ok(components[..n_components].join("."));
+                    }
+                };
+
+                match components.get(index) {
+                    Some(component) => Ok((*component).to_owned()),
+                    None => Err(Diagnostic::error(
+                        ErrorCode::InvalidArgument,
+                        format!("release '{}' has no {} component", self.release, arg),
+                    ).at(&args[0])
+                        .with_note("set the project's release in config.toml")),
                 }
-
-                let n_components = arg.matches('.').count() + 1;
-                Ok(self.version[..n_components].join("."))
             }
-            _ => Err(()),
+            _ => Err(Diagnostic::too_many_arguments(1)),
         }
     }
 }
 
+/// The admonitions available in every project, as (name, title) pairs.
+pub const DEFAULT_ADMONITIONS: &[(&str, &str)] = &[
+    ("note", "Note"),
+    ("warning", "Warning"),
+    ("tip", "Tip"),
+    ("important", "Important"),
+    ("caution", "Caution"),
+    ("see-also", "See Also"),
+];
+
+/// How a kind of admonition is presented, as given in config.toml or theme.toml.
+/// Unset fields fall back to the defaults.
+#[derive(Clone, Debug, Default, Deserialize, Hash, PartialEq)]
+pub struct AdmonitionConfig {
+    pub title: Option<String>,
+    pub class: Option<String>,
+    pub icon: Option<String>,
+    pub collapsible: Option<bool>,
+    pub collapsed: Option<bool>,
+}
+
+impl AdmonitionConfig {
+    /// Override this configuration with any fields set in another.
+    pub fn merge(&mut self, other: AdmonitionConfig) {
+        self.title = other.title.or_else(|| self.title.take());
+        self.class = other.class.or_else(|| self.class.take());
+        self.icon = other.icon.or_else(|| self.icon.take());
+        self.collapsible = other.collapsible.or(self.collapsible);
+        self.collapsed = other.collapsed.or(self.collapsed);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Collapse {
+    Never,
+    Open,
+    Closed,
+}
+
 pub struct Admonition {
     title: String,
     class: String,
+    icon: Option<String>,
+    collapse: Collapse,
 }
 
 impl Admonition {
-    pub fn new(title: &str, class: &str) -> Self {
+    /// Create an admonition named name, such as "see-also". Without a configured
+    /// title or class, it is titled "See Also" and has the class "see-also".
+    pub fn from_config(name: &str, config: &AdmonitionConfig) -> Self {
+        let title = config.title.to_owned().unwrap_or_else(|| {
+            name.split('-')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+
+        let collapse = if config.collapsed.unwrap_or(false) {
+            Collapse::Closed
+        } else if config.collapsible.unwrap_or(false) {
+            Collapse::Open
+        } else {
+            Collapse::Never
+        };
+
         Admonition {
-            title: title.to_owned(),
-            class: class.to_owned(),
+            title,
+            class: config.class.to_owned().unwrap_or_else(|| name.to_owned()),
+            icon: config.icon.to_owned(),
+            collapse,
         }
     }
 }
 
 impl DirectiveHandler for Admonition {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        // A leading collapsible or collapsed option overrides the configuration
+        let mut collapse = self.collapse;
+        let mut args = args;
+        while args.len() > 1 {
+            match args[0].value {
+                NodeValue::Owned(ref s) if s == "collapsible" => collapse = Collapse::Open,
+                NodeValue::Owned(ref s) if s == "collapsed" => collapse = Collapse::Closed,
+                _ => break,
+            }
+            args = &args[1..];
+        }
+
         let mut title = self.title.to_owned();
         let raw_body = match args.len() {
             1 => worker.evaluate(&args[0]),
@@ -133,20 +419,40 @@ impl DirectiveHandler for Admonition {
                 title = worker.evaluate(&args[0]);
                 worker.evaluate(&args[1])
             }
-            _ => return Err(()),
+            0 => return Err(Diagnostic::missing_argument("body")),
+            _ => return Err(Diagnostic::too_many_arguments(2)),
+        };
+
+        if let Some(ref icon) = self.icon {
+            title = format!(
+                r#"<span class="admonition-icon" aria-hidden="true">{}</span>{}"#,
+                icon,
+                title
+            );
+        }
+
+        let (container, title_tag, open) = match collapse {
+            Collapse::Never => ("div", "span", ""),
+            Collapse::Open => ("details", "summary", " open"),
+            Collapse::Closed => ("details", "summary", ""),
         };
 
         Ok(format!(
             concat!(
-                "<div class=\"admonition admonition-{}\">",
-                "<span class=\"admonition-title admonition-title-{}\">",
-                "{}</span>",
-                "{}</div>\n"
+                "<{} class=\"admonition admonition-{}\"{}>",
+                "<{} class=\"admonition-title admonition-title-{}\">",
+                "{}</{}>",
+                "{}</{}>\n"
             ),
+            container,
             self.class,
+            open,
+            title_tag,
             self.class,
             title,
-            &raw_body
+            title_tag,
+            &raw_body,
+            container
         ))
     }
 }
@@ -154,7 +460,7 @@ impl DirectiveHandler for Admonition {
 pub struct Concat;
 
 impl DirectiveHandler for Concat {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
         Ok(concat_nodes(&mut iter, worker, ""))
     }
@@ -172,31 +478,32 @@ impl Template {
 }
 
 impl DirectiveHandler for Template {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
-        let checkers = self.checkers.iter().map(Some).chain(iter::repeat(None));
-
-        let args: Result<Vec<String>, ()> = args.iter()
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        let nodes = args;
+        let mut args: Vec<String> = nodes
+            .iter()
             .map(|node| match node.value {
                 NodeValue::Owned(ref s) => s.to_owned(),
                 NodeValue::Children(_) => worker.evaluate(node),
             })
-            .chain(iter::repeat("".to_owned()))
-            .zip(checkers)
-            .map(|(arg, checker)| match checker {
-                Some(checker) => if checker.is_match(&arg) {
-                    Ok(arg)
-                } else {
-                    Err(())
-                },
-                _ => Ok(arg),
-            })
-            .take(cmp::max(args.len(), self.checkers.len()))
             .collect();
 
-        let args = match args {
-            Ok(args) => args,
-            Err(_) => return Err(()),
-        };
+        let n_args = cmp::max(args.len(), self.checkers.len());
+        args.resize(n_args, String::new());
+
+        for (i, (arg, checker)) in args.iter().zip(&self.checkers).enumerate() {
+            if !checker.is_match(arg) {
+                let diagnostic = Diagnostic::error(
+                    ErrorCode::ArgumentMismatch,
+                    format!("argument {} does not match regex `{}`", i + 1, checker.as_str()),
+                );
+
+                return Err(match nodes.get(i) {
+                    Some(node) => diagnostic.at(node),
+                    None => diagnostic,
+                });
+            }
+        }
 
         lazy_static! {
             static ref RE: Regex = Regex::new(r#"\$\{(\d)\}"#).unwrap();
@@ -217,24 +524,31 @@ impl DirectiveHandler for Template {
 pub struct DefineTemplate;
 
 impl DirectiveHandler for DefineTemplate {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let name = consume_string(&mut iter, worker).ok_or(())?;
-        let template_text = consume_string(&mut iter, worker).ok_or(())?;
+        let name = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("name"))?;
+        let template_text = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("template"))?;
 
-        let checkers: Result<Vec<Regex>, ()> = iter.map(|node| {
+        let mut checkers = vec![];
+        for node in iter {
             let pattern_string = match node.value {
                 NodeValue::Owned(ref s) => s.to_owned(),
                 NodeValue::Children(_) => worker.evaluate(node),
             };
 
-            Regex::new(&pattern_string).or(Err(()))
-        }).collect();
-
-        let checkers = match checkers {
-            Ok(c) => c,
-            Err(_) => return Err(()),
-        };
+            match Regex::new(&pattern_string) {
+                Ok(checker) => checkers.push(checker),
+                Err(err) => {
+                    return Err(Diagnostic::error(
+                        ErrorCode::InvalidRegex,
+                        format!("invalid regex `{}`", pattern_string),
+                    ).at(node)
+                        .with_note(err.to_string()))
+                }
+            }
+        }
 
         worker.register(name, Box::new(Template::new(template_text, checkers)));
         Ok("".to_owned())
@@ -244,48 +558,57 @@ impl DirectiveHandler for DefineTemplate {
 pub struct DefinitionList;
 
 impl DirectiveHandler for DefinitionList {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let segments: Result<Vec<_>, _> = args.iter()
             .map(|node| match node.value {
-                NodeValue::Owned(_) => Err(()),
-                NodeValue::Children(ref children) => {
-                    if children.len() != 2 {
-                        return Err(());
-                    }
-
+                NodeValue::Children(ref children) if children.len() == 2 => {
                     let term = worker.evaluate(&children[0]);
                     let body = worker.evaluate(&children[1]);
                     Ok(format!("<dt>{}</dt><dd>{}</dd>", term, &body))
                 }
+                _ => Err(Diagnostic::error(
+                    ErrorCode::InvalidArgument,
+                    "expected a term and its definition",
+                ).at(node)),
             })
             .collect();
 
-        match segments {
-            Ok(s) => Ok(s.concat()),
-            Err(_) => Err(()),
-        }
+        Ok(segments?.concat())
     }
 }
 
 pub struct Include;
 
 impl DirectiveHandler for Include {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
-        if args.len() != 1 {
-            return Err(());
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        match args.len() {
+            0 => return Err(Diagnostic::missing_argument("path")),
+            1 => (),
+            _ => return Err(Diagnostic::too_many_arguments(1)),
         }
 
         let path = worker.evaluate(&args[0]);
         let path = worker.get_source_path(&args[0], &path);
-        let node = match worker.parser.parse(path.as_ref()) {
-            Ok(n) => n,
-            Err(msg) => {
-                let msg = format!("Failed to parse '{}': {}", path.to_string_lossy(), msg);
-                worker.error(&args[0], &msg);
-                return Err(());
-            }
+        let failed = || {
+            Diagnostic::error(
+                ErrorCode::IncludeFailed,
+                format!("failed to include '{}'", path.to_string_lossy()),
+            ).at(&args[0])
+        };
+
+        let (node, diagnostics) = match worker.parser.parse(path.as_ref()) {
+            Ok(result) => result,
+            Err(msg) => return Err(failed().with_note(msg)),
         };
 
+        for diagnostic in &diagnostics {
+            worker.report(diagnostic);
+        }
+
+        if diagnostics.iter().any(|d| d.level == Level::Error) {
+            return Err(failed().with_note("the included file has syntax errors"));
+        }
+
         Ok(worker.evaluate(&node))
     }
 }
@@ -293,7 +616,7 @@ impl DirectiveHandler for Include {
 pub struct Import;
 
 impl DirectiveHandler for Import {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let include = Include;
         include.handle(worker, args)?;
 
@@ -301,23 +624,173 @@ impl DirectiveHandler for Import {
     }
 }
 
+/// Remove the indentation that every non-blank line has in common.
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|line| if line.trim().is_empty() {
+            ""
+        } else {
+            line.get(indent..).unwrap_or_else(|| line.trim_start())
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Select the lines after the first one containing start_after, up to the next one
+/// containing end_before.
+fn select_between<'a>(
+    lines: &[&'a str],
+    start_after: Option<&str>,
+    end_before: Option<&str>,
+) -> Result<Vec<&'a str>, String> {
+    let start = match start_after {
+        Some(marker) => match lines.iter().position(|line| line.contains(marker)) {
+            Some(i) => i + 1,
+            None => return Err(format!("start marker '{}' not found", marker)),
+        },
+        None => 0,
+    };
+
+    let lines = &lines[start..];
+    let end = match end_before {
+        Some(marker) => match lines.iter().position(|line| line.contains(marker)) {
+            Some(i) => i,
+            None => return Err(format!("end marker '{}' not found", marker)),
+        },
+        None => lines.len(),
+    };
+
+    Ok(lines[..end].to_vec())
+}
+
+/// A highlighted block of code read from a file relative to the current source.
+/// Takes the same options as code, along with language=, lines=, start-after=, and
+/// end-before=.
+pub struct LiteralInclude;
+
+impl LiteralInclude {
+    const NAMES: &'static str = "language, lines, start-after, end-before, linenos, \
+                                 lineno-start, emphasize, caption, filename, or id";
+}
+
+impl DirectiveHandler for LiteralInclude {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        if args.is_empty() {
+            return Err(Diagnostic::missing_argument("path"));
+        }
+
+        let path = worker.evaluate(&args[0]);
+        let path = worker.get_source_path(&args[0], &path);
+
+        let mut options = CodeBlockOptions::default();
+        let mut language = None;
+        let mut line_numbers = None;
+        let mut start_after = None;
+        let mut end_before = None;
+        for node in &args[1..] {
+            let (name, value) = match options.apply(worker, node)? {
+                Some(option) => option,
+                None => continue,
+            };
+
+            let invalid =
+                |message: String| Diagnostic::error(ErrorCode::InvalidArgument, message).at(node);
+            if !["language", "lines", "start-after", "end-before"].contains(&name.as_str()) {
+                return Err(unknown_option(node, &name, Self::NAMES));
+            }
+
+            let value =
+                value.ok_or_else(|| invalid(format!("option '{}' requires a value", name)))?;
+
+            match name.as_ref() {
+                "language" => language = Some((value, node)),
+                "lines" => {
+                    let mut lines: Vec<_> = parse_line_ranges(&value)
+                        .map_err(&invalid)?
+                        .into_iter()
+                        .collect();
+                    lines.sort();
+                    line_numbers = Some(lines);
+                }
+                "start-after" => start_after = Some(value),
+                _ => end_before = Some(value),
+            }
+        }
+
+        let failed = || {
+            Diagnostic::error(
+                ErrorCode::IncludeFailed,
+                format!("failed to include '{}'", path.to_string_lossy()),
+            ).at(&args[0])
+        };
+
+        let text = fs::read_to_string(&path).map_err(|err| failed().with_note(err.to_string()))?;
+        worker.add_dependency(path.to_owned());
+
+        let mut lines: Vec<_> = text.lines().collect();
+        if let Some(line_numbers) = line_numbers {
+            if let Some(&last) = line_numbers.last() {
+                if last > lines.len() {
+                    return Err(failed().with_note(format!(
+                        "line {} is past the end of the file, which has {} lines",
+                        last,
+                        lines.len()
+                    )));
+                }
+            }
+
+            lines = line_numbers.iter().map(|&n| lines[n - 1]).collect();
+        }
+
+        let lines = select_between(
+            &lines,
+            start_after.as_deref(),
+            end_before.as_deref(),
+        ).map_err(|message| failed().with_note(message))?;
+
+        let (language, language_node) = match language {
+            Some((language, node)) => (language, node),
+            None => {
+                let extension = path.extension().map(|ext| ext.to_string_lossy().into_owned());
+                (extension.unwrap_or_else(|| "txt".to_owned()), &args[0])
+            }
+        };
+
+        options.render(worker, language_node, &language, &dedent(&lines))
+    }
+}
+
 pub struct Let;
 
 impl DirectiveHandler for Let {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
-        if args.len() < 1 {
-            return Err(());
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        if args.is_empty() {
+            return Err(Diagnostic::missing_argument("bindings"));
         }
 
         let mut variables = Vec::new();
         let kvs = &args[0];
+        let invalid_bindings = || {
+            Diagnostic::error(ErrorCode::InvalidArgument, "expected a list of name-value pairs")
+                .at(kvs)
+        };
+
         match kvs.value {
             NodeValue::Owned(_) => {
-                return Err(());
+                return Err(invalid_bindings());
             }
             NodeValue::Children(ref children) => {
                 if children.len() % 2 != 0 {
-                    return Err(());
+                    return Err(invalid_bindings()
+                        .with_note("found a name without a value"));
                 }
 
                 for pair in children.chunks(2) {
@@ -325,7 +798,7 @@ impl DirectiveHandler for Let {
                     let evaluated_value = Arc::new(StoredValue::Node(Node::new_string(
                         worker.evaluate(&pair[1]),
                         pair[1].file_id,
-                        pair[1].lineno,
+                        pair[1].span,
                     )));
 
                     let entry = worker.ctx.entry(evaluated_key.to_owned());
@@ -361,20 +834,25 @@ impl DirectiveHandler for Let {
 pub struct Define;
 
 impl DirectiveHandler for Define {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let arg1 = consume_string(&mut iter, worker).ok_or(())?;
-        let arg2 = iter.next().ok_or(())?;
+        let arg1 = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("name"))?;
+        let arg2 = iter.next()
+            .ok_or_else(|| Diagnostic::missing_argument("value"))?;
         let arg3 = iter.next();
 
         if iter.next().is_some() {
-            return Err(());
+            return Err(Diagnostic::too_many_arguments(3));
         }
 
         let (eager, key, value_node) = match arg3 {
             Some(value) => {
                 if arg1 != "evaluate" {
-                    return Err(());
+                    return Err(Diagnostic::error(
+                        ErrorCode::InvalidArgument,
+                        format!("expected 'evaluate', found '{}'", arg1),
+                    ).at(&args[0]));
                 }
 
                 (true, worker.evaluate(arg2), value)
@@ -387,13 +865,13 @@ impl DirectiveHandler for Define {
             Node::new(
                 NodeValue::Owned(evaluated),
                 value_node.file_id,
-                value_node.lineno,
+                value_node.span,
             )
         } else {
             Node::new(
                 value_node.value.clone(),
                 value_node.file_id,
-                value_node.lineno,
+                value_node.span,
             )
         };
 
@@ -407,9 +885,9 @@ impl DirectiveHandler for Define {
 pub struct ThemeConfig;
 
 impl DirectiveHandler for ThemeConfig {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         if args.len() % 2 != 0 {
-            return Err(());
+            return Err(Diagnostic::missing_argument("value").at(&args[args.len() - 1]));
         }
 
         for pair in args.chunks(2) {
@@ -428,21 +906,24 @@ impl DirectiveHandler for ThemeConfig {
 pub struct TocTree;
 
 impl DirectiveHandler for TocTree {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         for arg in args {
             match arg.value {
                 NodeValue::Owned(ref slug) => {
-                    worker.add_to_toctree(Slug::new(slug.to_owned()), None);
+                    worker.add_to_toctree(arg, Slug::new(slug.to_owned()), None);
                 }
                 NodeValue::Children(ref children) => {
                     if children.len() != 2 {
-                        return Err(());
+                        return Err(Diagnostic::error(
+                            ErrorCode::InvalidArgument,
+                            "expected a title and a slug",
+                        ).at(arg));
                     }
 
                     let title = worker.evaluate(&children[0]);
                     let slug = worker.evaluate(&children[1]);
 
-                    worker.add_to_toctree(Slug::new(slug), Some(title));
+                    worker.add_to_toctree(arg, Slug::new(slug), Some(title));
                 }
             }
         }
@@ -451,6 +932,9 @@ impl DirectiveHandler for TocTree {
     }
 }
 
+/// A heading, given as (:h<level> [<id>] <title>). Every heading can be referred to
+/// as <slug>#<anchor>, such as (:ref tutorials/install#ref-macos), and a heading
+/// with an id also by that id alone.
 pub struct Heading {
     level: i8,
     prefix : String,
@@ -483,23 +967,43 @@ impl Heading {
 }
 
 impl DirectiveHandler for Heading {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        lazy_static! {
+            static ref PAT_TAGS: Regex = Regex::new("<[^>]*>").unwrap();
+            static ref PAT_LINKS: Regex = Regex::new(r"</?a(\s[^>]*)?>").unwrap();
+        }
+
         let mut iter = args.iter();
-        let arg1 = consume_string(&mut iter, worker).ok_or(())?;
+        let arg1 = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("title"))?;
         let arg2 = consume_string(&mut iter, worker);
 
-        let (title, refdef) = match arg2 {
-            Some(title) => {
-                let refdef = RefDef::new(&title, worker.get_slug());
-                worker.insert_refdef(format!("{}{}", self.prefix, arg1), refdef);
-                (title, arg1)
+        let (title, id) = match arg2 {
+            Some(title) => (title, Some(arg1)),
+            None => (arg1, None),
+        };
+
+        // Links in the title are neither part of its id nor of references to it
+        let text = worker.strip_placeholders(&title);
+        let slug = worker.get_slug().to_owned();
+        let refdef_title = PAT_LINKS.replace_all(&text, "").into_owned();
+        let anchor = match id {
+            Some(ref id) if worker.claim_anchor(&args[0], id) => {
+                let refdef = RefDef::new(&refdef_title, &slug).with_fragment(id.as_str());
+                worker.insert_refdef(&args[0], format!("{}{}", self.prefix, id), refdef);
+                id.to_owned()
             }
+            // The first heading with a repeated id keeps the reference
+            Some(ref id) => worker.unique_anchor(id.to_owned()),
             None => {
-                let title_id = format!("{}{}", self.prefix, Self::title_to_id(&arg1));
-                (arg1, title_id)
+                let text = PAT_TAGS.replace_all(&text, "");
+                worker.unique_anchor(format!("{}{}", self.prefix, Self::title_to_id(&text)))
             }
         };
 
+        let refdef = RefDef::new(&refdef_title, &slug).with_fragment(anchor.as_str());
+        worker.insert_refdef(&args[0], format!("{}{}#{}", self.prefix, slug, anchor), refdef);
+
         if !worker.theme_config.contains_key("title") {
             worker.theme_config.insert(
                 "title".to_owned(),
@@ -513,7 +1017,7 @@ impl DirectiveHandler for Heading {
             r#"{}<h{} id="{}">{}</h{}>"#,
             prefix,
             self.level,
-            escape_string(&refdef),
+            escape_string(&anchor),
             title,
             self.level
         ))
@@ -535,13 +1039,16 @@ impl RefDefDirective {
 }
 
 impl DirectiveHandler for RefDefDirective {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let id = format!("{}{}", self.prefix, consume_string(&mut iter, worker).ok_or(())?);
-        let title = consume_string(&mut iter, worker).ok_or(())?;
+        let id = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("id"))?;
+        let id = format!("{}{}", self.prefix, id);
+        let title = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("title"))?;
 
         let refdef = RefDef::new(&title, worker.get_slug());
-        worker.insert_refdef(id, refdef);
+        worker.insert_refdef(&args[0], id, refdef);
 
         Ok(String::new())
     }
@@ -561,9 +1068,11 @@ impl RefDirective {
 }
 
 impl DirectiveHandler for RefDirective {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let refid = format!("{}{}", self.prefix, consume_string(&mut iter, worker).ok_or(())?);
+        let refid = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("id"))?;
+        let refid = format!("{}{}", self.prefix, refid);
 
         let title = match consume_string(&mut iter, worker) {
             Some(t) => t,
@@ -576,17 +1085,58 @@ impl DirectiveHandler for RefDirective {
     }
 }
 
+/// A footnote marker. With one argument, the argument is the footnote's body; with
+/// two, the first is a name that footnote-ref can refer to.
+pub struct Footnote;
+
+impl DirectiveHandler for Footnote {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        let (name, body) = match args.len() {
+            0 => return Err(Diagnostic::missing_argument("body")),
+            1 => (None, &args[0]),
+            2 => (Some(worker.evaluate(&args[0])), &args[1]),
+            _ => return Err(Diagnostic::too_many_arguments(2)),
+        };
+
+        let body = worker.evaluate(body);
+        let node = &args[0];
+        worker.add_footnote(node, name, Some(body))
+    }
+}
+
+/// Another marker for a named footnote.
+pub struct FootnoteRef;
+
+impl DirectiveHandler for FootnoteRef {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        let mut iter = args.iter();
+        let name = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("name"))?;
+
+        if iter.next().is_some() {
+            return Err(Diagnostic::too_many_arguments(1));
+        }
+
+        worker.add_footnote(&args[0], Some(name), None)
+    }
+}
+
 pub struct Steps;
 
 impl DirectiveHandler for Steps {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut result: Vec<Cow<str>> = Vec::with_capacity(2 + (args.len() * 4));
         result.push(Cow::from(r#"<div class="steps">"#));
 
         for (i, step_node) in args.iter().enumerate() {
+            let invalid_step = || {
+                Diagnostic::error(ErrorCode::InvalidArgument, "expected a step title and body")
+                    .at(step_node)
+            };
+
             let parse_args = |args: &[Node], worker: &mut Worker| {
                 if args.len() != 3 {
-                    return Err(());
+                    return Err(invalid_step());
                 }
 
                 Ok((worker.evaluate(&args[1]), worker.evaluate(&args[2])))
@@ -596,15 +1146,20 @@ impl DirectiveHandler for Steps {
                 NodeValue::Owned(ref s) => {
                     let stored_value = match worker.ctx.get(s) {
                         Some(v) => Arc::clone(v),
-                        None => return Err(()),
+                        None => {
+                            return Err(Diagnostic::error(
+                                ErrorCode::UnknownName,
+                                format!("unknown name '{}'", s),
+                            ).at(step_node))
+                        }
                     };
 
                     match *stored_value {
                         StoredValue::Node(ref node) => match node.value {
-                            NodeValue::Owned(_) => return Err(()),
+                            NodeValue::Owned(_) => return Err(invalid_step()),
                             NodeValue::Children(ref children) => parse_args(children, worker),
                         },
-                        _ => return Err(()),
+                        _ => return Err(invalid_step()),
                     }
                 }
                 NodeValue::Children(ref children) => parse_args(children, worker),
@@ -627,30 +1182,219 @@ impl DirectiveHandler for Steps {
     }
 }
 
+/// Number a figure or table on the current page, and register its id, if any, as a
+/// reference that resolves to the number. Returns the contents of its caption.
+fn number_caption(
+    worker: &mut Worker,
+    counter: Counter,
+    caption: Option<&str>,
+    id: Option<&(String, Node)>,
+) -> String {
+    let (n, label) = worker.next_number(counter);
+    if let Some((id, node)) = id {
+        let refdef = RefDef::new(caption.unwrap_or(id), worker.get_slug())
+            .with_fragment(id.as_str())
+            .with_number(counter, n);
+        worker.insert_refdef(node, format!("ref-{}", id), refdef);
+    }
+
+    let mut result = format!(r#"<span class="caption-number">{}</span>"#, label);
+    if let Some(caption) = caption {
+        result.push(' ');
+        result.push_str(caption);
+    }
+
+    result
+}
+
+/// An image, given as (:figure <src> <alt> [width]), optionally followed by an
+/// id=<id> option and a (:caption ...) element. Figures with a caption or an id are
+/// numbered, and (:ref <id>) links to them by their number.
 pub struct Figure;
 
 impl DirectiveHandler for Figure {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let src = escape_string(&consume_string(&mut iter, worker).ok_or(())?);
-        let src = worker.add_asset(&src)?;
-        let alt = escape_string(&consume_string(&mut iter, worker).ok_or(())?);
+        let src = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("src"))?;
+        let src = escape_string(&worker.add_asset(&args[0], &src)?);
+        let alt = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("alt"))?;
+        let alt = escape_string(&alt);
+
+        let mut width = None;
+        let mut id = None;
+        let mut caption = None;
+        for node in iter {
+            match node.value {
+                NodeValue::Owned(ref option) => match split_option(option) {
+                    ("id", Some(value)) => id = Some((value.to_owned(), node.clone())),
+                    (value, None) if width.is_none() && id.is_none() => {
+                        let width_integer = value.parse::<u16>().map_err(|_| {
+                            Diagnostic::error(
+                                ErrorCode::InvalidArgument,
+                                format!("invalid width '{}'", value),
+                            ).at(node)
+                                .with_note("width must be a number of pixels")
+                        })?;
+                        width = Some(width_integer);
+                    }
+                    (name, _) => return Err(unknown_option(node, name, "id")),
+                },
+                NodeValue::Children(ref children) => {
+                    let mut children = children.iter();
+                    match consume_string(&mut children, worker) {
+                        Some(ref kind) if kind == "caption" => {
+                            caption = Some(concat_nodes(&mut children, worker, " "));
+                        }
+                        kind => {
+                            return Err(Diagnostic::error(
+                                ErrorCode::InvalidArgument,
+                                format!("unknown figure element '{}'", kind.unwrap_or_default()),
+                            ).at(node)
+                                .with_note("expected caption"))
+                        }
+                    }
+                }
+            }
+        }
 
-        let width = consume_string(&mut iter, worker);
         let width_term = match width {
-            Some(ref s) => {
-                let width_integer = s.parse::<u16>().ok().ok_or(())?;
-                Cow::from(format!(" width={}px", width_integer))
-            }
+            Some(width) => Cow::from(format!(" width={}px", width)),
             None => Cow::from(""),
         };
 
-        Ok(format!(
-            r#"<img src="{}" alt="{}"{}>"#,
-            src,
-            alt,
-            width_term
-        ))
+        let id = id.filter(|(id, node)| worker.claim_anchor(node, id));
+        let mut result = String::from("<figure");
+        if let Some((ref id, _)) = id {
+            result.push_str(&format!(r#" id="{}""#, escape_string(id)));
+        }
+        result.push_str(&format!(r#"><img src="{}" alt="{}"{}>"#, src, alt, width_term));
+
+        if caption.is_some() || id.is_some() {
+            let caption = number_caption(worker, Counter::Figure, caption.as_deref(), id.as_ref());
+            result.push_str(&format!("<figcaption>{}</figcaption>", caption));
+        }
+        result.push_str("</figure>");
+
+        Ok(result)
+    }
+}
+
+/// A table built from (:caption ...), (:align ...), (:header ...), and (:row ...)
+/// elements, along with an optional id=<id> option. Tables are numbered like figures.
+pub struct Table;
+
+impl Table {
+    fn parse_alignment(alignment: &str) -> Result<Option<&'static str>, Diagnostic> {
+        match alignment {
+            "left" => Ok(Some("left")),
+            "center" => Ok(Some("center")),
+            "right" => Ok(Some("right")),
+            "default" => Ok(None),
+            _ => Err(Diagnostic::error(
+                ErrorCode::InvalidArgument,
+                format!("unknown alignment '{}'", alignment),
+            ).with_note("expected one of left, center, right, or default")),
+        }
+    }
+
+    fn render_row(
+        worker: &mut Worker,
+        cells: &[Node],
+        tag: &str,
+        alignment: &[Option<&'static str>],
+    ) -> String {
+        let mut result = String::from("<tr>");
+        for (i, cell) in cells.iter().enumerate() {
+            let body = worker.evaluate(cell);
+            match alignment.get(i) {
+                Some(&Some(align)) => result.push_str(&format!(
+                    r#"<{} style="text-align:{}">{}</{}>"#,
+                    tag,
+                    align,
+                    body,
+                    tag
+                )),
+                _ => result.push_str(&format!("<{}>{}</{}>", tag, body, tag)),
+            }
+        }
+
+        result.push_str("</tr>");
+        result
+    }
+}
+
+impl DirectiveHandler for Table {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        let mut caption = None;
+        let mut id = None;
+        let mut alignment = vec![];
+        let mut header_rows = vec![];
+        let mut body_rows = vec![];
+
+        for node in args {
+            let invalid_element = |kind: &str| {
+                Diagnostic::error(
+                    ErrorCode::InvalidArgument,
+                    format!("unknown table element '{}'", kind),
+                ).at(node)
+                    .with_note("expected one of caption, align, header, or row")
+            };
+
+            let children = match node.value {
+                NodeValue::Owned(ref s) => match split_option(s) {
+                    ("id", Some(value)) => {
+                        id = Some((value.to_owned(), node.clone()));
+                        continue;
+                    }
+                    _ => return Err(invalid_element(s)),
+                },
+                NodeValue::Children(ref children) => children,
+            };
+
+            let mut iter = children.iter();
+            let kind = consume_string(&mut iter, worker).unwrap_or_default();
+            match kind.as_ref() {
+                "caption" => caption = Some(concat_nodes(&mut iter, worker, " ")),
+                "align" => {
+                    alignment = iter.map(|node| {
+                        Self::parse_alignment(&worker.evaluate(node)).map_err(|d| d.at(node))
+                    }).collect::<Result<Vec<_>, _>>()?;
+                }
+                "header" => header_rows.push(iter.as_slice()),
+                "row" => body_rows.push(iter.as_slice()),
+                _ => return Err(invalid_element(&kind)),
+            }
+        }
+
+        let id = id.filter(|(id, node)| worker.claim_anchor(node, id));
+        let mut result = String::from("<table");
+        if let Some((ref id, _)) = id {
+            result.push_str(&format!(r#" id="{}""#, escape_string(id)));
+        }
+        result.push('>');
+
+        if caption.is_some() || id.is_some() {
+            let caption = number_caption(worker, Counter::Table, caption.as_deref(), id.as_ref());
+            result.push_str(&format!("<caption>{}</caption>", caption));
+        }
+
+        if !header_rows.is_empty() {
+            result.push_str("<thead>");
+            for row in header_rows {
+                result.push_str(&Self::render_row(worker, row, "th", &alignment));
+            }
+            result.push_str("</thead>");
+        }
+
+        result.push_str("<tbody>");
+        for row in body_rows {
+            result.push_str(&Self::render_row(worker, row, "td", &alignment));
+        }
+        result.push_str("</tbody></table>");
+
+        Ok(result)
     }
 }
 
@@ -665,7 +1409,7 @@ impl FormattingMarker {
 }
 
 impl DirectiveHandler for FormattingMarker {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
         let body = concat_nodes(&mut iter, worker, " ");
         Ok(format!("<{}>{}</{}>", self.tag, body, self.tag))
@@ -675,9 +1419,10 @@ impl DirectiveHandler for FormattingMarker {
 pub struct Link;
 
 impl DirectiveHandler for Link {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let mut iter = args.iter();
-        let href = consume_string(&mut iter, worker).ok_or(())?;
+        let href = consume_string(&mut iter, worker)
+            .ok_or_else(|| Diagnostic::missing_argument("href"))?;
         let href = escape_string(&href);
         let body = concat_nodes(&mut iter, worker, " ");
         let b = if body.is_empty() { &href } else { &body };
@@ -696,7 +1441,7 @@ impl List {
 }
 
 impl DirectiveHandler for List {
-    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, ()> {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
         let body: Vec<String> = args.iter()
             .map(|node| {
                 let item_body = worker.evaluate(node);
@@ -716,14 +1461,34 @@ impl DirectiveHandler for List {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{env, fs, process};
+    use std::fs::File;
+    use std::path::PathBuf;
+    use diagnostic::{Level, Location};
+    use lex::{Position, Span};
     use evaluator::Evaluator;
+    use highlighter::{self, SyntaxHighlighter};
+    use page::{Asset, Page};
 
     fn node_string(s: &str) -> Node {
-        Node::new_string(s, 0, -1)
+        Node::new_string(s, 0, Span::unknown())
     }
 
     fn node_children(nodes: Vec<Node>) -> Node {
-        Node::new_children(nodes, 0, -1)
+        Node::new_children(nodes, 0, Span::unknown())
+    }
+
+    /// Resolve the placeholders in a body compiled for the given page.
+    fn link(evaluator: &Evaluator, slug: &str, body: &str) -> String {
+        let page = Page {
+            source_path: PathBuf::from(format!("{}.rocket", slug)),
+            slug: Slug::new(slug.to_owned()),
+            body: body.to_owned(),
+            theme_config: serde_json::map::Map::new(),
+            assets: vec![],
+        };
+
+        evaluator.substitute(&page)
     }
 
     #[test]
@@ -744,12 +1509,227 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_code() {
+        let evaluator = Evaluator::new();
+        let mut worker = Worker::new(&evaluator);
+        let handler = Code;
+
+        assert_eq!(
+            handler.handle(&mut worker, &[]).unwrap_err().code,
+            ErrorCode::MissingArgument
+        );
+        assert!(
+            handler
+                .handle(&mut worker, &[node_string("Python"), node_string("pass")])
+                .is_ok()
+        );
+
+        let diagnostic = handler
+            .handle(&mut worker, &[node_string("pyhton"), node_string("pass")])
+            .unwrap_err();
+        assert_eq!(diagnostic.code, ErrorCode::UnknownLanguage);
+        assert_eq!(diagnostic.message, "unknown language 'pyhton'");
+        assert_eq!(diagnostic.notes, vec!["did you mean 'python'?".to_owned()]);
+
+        // Arguments that aren't options are all code
+        let code = |args: &[&str], worker: &mut Worker| {
+            let nodes: Vec<_> = args.iter().map(|arg| node_string(arg)).collect();
+            handler.handle(worker, &nodes)
+        };
+        assert_eq!(
+            code(&["txt", "a", "b"], &mut worker),
+            code(&["txt", "ab"], &mut worker)
+        );
+        assert_eq!(
+            code(&["txt", "linenos", "x=1", "id"], &mut worker),
+            code(&["txt", "linenos", "x=1id"], &mut worker)
+        );
+        assert!(code(&["txt", "id"], &mut worker).unwrap().contains("id"));
+
+        let diagnostic = handler
+            .handle(
+                &mut worker,
+                &[node_string("python"), node_string("emphasize=0"), node_string("pass")],
+            )
+            .unwrap_err();
+        assert_eq!(diagnostic.message, "invalid line number '0'");
+    }
+
+    #[test]
+    fn test_line_ranges() {
+        let lines = parse_line_ranges("3-5, 9").unwrap();
+        let mut lines: Vec<_> = lines.into_iter().collect();
+        lines.sort();
+        assert_eq!(lines, vec![3, 4, 5, 9]);
+
+        assert!(parse_line_ranges("5-3").is_err());
+        assert!(parse_line_ranges("a").is_err());
+        assert!(parse_line_ranges("").is_err());
+    }
+
+    #[test]
+    fn test_code_options() {
+        let mut evaluator = Evaluator::new();
+        {
+            let mut worker = Worker::new(&mut evaluator);
+            worker.set_slug(Slug::new("index".to_owned()));
+            let handler = Code;
+
+            let html = handler
+                .handle(
+                    &mut worker,
+                    &[
+                        node_string("python"),
+                        node_string("lineno-start=10"),
+                        node_string("emphasize=2"),
+                        node_string("x = 1\ny = 2"),
+                    ],
+                )
+                .unwrap();
+            assert!(html.starts_with("<pre"));
+            assert!(html.contains(r#"<span class="code-lineno">10</span>"#));
+            assert!(html.contains(
+                r#"<span class="code-line code-line-emphasized"><span class="code-lineno">11</span>"#
+            ));
+            assert!(!html.contains("<figure"));
+
+            let html = handler
+                .handle(
+                    &mut worker,
+                    &[
+                        node_string("python"),
+                        node_string("filename=main.py"),
+                        node_string("caption=The entry point"),
+                        node_string("id=main"),
+                        node_string("pass"),
+                    ],
+                )
+                .unwrap();
+            assert!(html.starts_with(concat!(
+                r#"<figure class="code-block" id="main"><figcaption>"#,
+                r#"<span class="code-filename">main.py</span>The entry point</figcaption><pre"#
+            )));
+            assert!(html.ends_with("</figure>"));
+        }
+
+        let refdef = evaluator.refdefs.read().unwrap()["ref-main"].clone();
+        assert_eq!(refdef.title, "The entry point");
+        assert_eq!(refdef.fragment, Some("main".to_owned()));
+    }
+
+    #[test]
+    fn test_literalinclude() {
+        let root = env::temp_dir().join(format!("rocket-test-literalinclude-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join("example.py"),
+            "import sys\n\ndef main():\n    # start\n    if sys.argv:\n\n        print(1)\n    # end\n",
+        ).unwrap();
+
+        let evaluator = Evaluator::new_with_options(root.to_owned(), true);
+        let mut worker = Worker::new(&evaluator);
+        worker.set_slug(Slug::new("index".to_owned()));
+        let handler = LiteralInclude;
+        let plain = |code: &str| {
+            SyntaxHighlighter::new(highlighter::DEFAULT_SYNTAX_THEME)
+                .highlight("py", code, &LineOptions::default())
+                .unwrap()
+        };
+
+        assert_eq!(
+            handler.handle(&mut worker, &[]).unwrap_err().code,
+            ErrorCode::MissingArgument
+        );
+        assert_eq!(
+            handler
+                .handle(&mut worker, &[node_string("/missing.py")])
+                .unwrap_err()
+                .code,
+            ErrorCode::IncludeFailed
+        );
+
+        assert_eq!(
+            handler.handle(
+                &mut worker,
+                &[
+                    node_string("/example.py"),
+                    node_string("start-after=# start"),
+                    node_string("end-before=# end"),
+                ]
+            ),
+            Ok(plain("if sys.argv:\n\n    print(1)"))
+        );
+        assert_eq!(
+            handler.handle(
+                &mut worker,
+                &[node_string("/example.py"), node_string("lines=3,1")]
+            ),
+            Ok(plain("import sys\ndef main():"))
+        );
+
+        let diagnostic = handler
+            .handle(
+                &mut worker,
+                &[node_string("/example.py"), node_string("lines=8-9")],
+            )
+            .unwrap_err();
+        assert_eq!(diagnostic.code, ErrorCode::IncludeFailed);
+        assert_eq!(
+            diagnostic.notes,
+            vec!["line 9 is past the end of the file, which has 8 lines".to_owned()]
+        );
+
+        let diagnostic = handler
+            .handle(
+                &mut worker,
+                &[node_string("/example.py"), node_string("end-before=# missing")],
+            )
+            .unwrap_err();
+        assert_eq!(diagnostic.notes, vec!["end marker '# missing' not found".to_owned()]);
+
+        assert_eq!(
+            handler
+                .handle(
+                    &mut worker,
+                    &[node_string("/example.py"), node_string("language")]
+                )
+                .unwrap_err()
+                .message,
+            "option 'language' requires a value"
+        );
+        assert_eq!(
+            handler
+                .handle(&mut worker, &[node_string("/example.py"), node_string("dedent")])
+                .unwrap_err()
+                .message,
+            "unknown option 'dedent'"
+        );
+
+        assert!(
+            handler
+                .handle(
+                    &mut worker,
+                    &[
+                        node_string("/example.py"),
+                        node_string("language=rust"),
+                        node_string("linenos"),
+                    ]
+                )
+                .unwrap()
+                .contains(r#"<span class="code-lineno">8</span>"#)
+        );
+        assert_eq!(worker.get_dependencies(), vec![root.join("example.py")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn test_version() {
         let mut evaluator = Evaluator::new();
         let mut worker = Worker::new(&mut evaluator);
         worker.register("concat", Box::new(Concat));
-        let handler = Version::new("3.4.0");
+        let handler = Version::new("3.4.0", "3.4.0");
 
         assert_eq!(handler.handle(&mut worker, &[]), Ok("3.4.0".to_owned()));
         assert_eq!(
@@ -778,23 +1758,101 @@ mod tests {
             ),
             Ok("3.4".to_owned())
         );
+
+        let handler = Version::new("3.4", "3.4.1-rc2");
+        let handle = |worker: &mut Worker, arg: &str| handler.handle(worker, &[node_string(arg)]);
+        assert_eq!(handler.handle(&mut worker, &[]), Ok("3.4".to_owned()));
+        assert_eq!(handle(&mut worker, "release"), Ok("3.4.1-rc2".to_owned()));
+        assert_eq!(handle(&mut worker, "major"), Ok("3".to_owned()));
+        assert_eq!(handle(&mut worker, "minor"), Ok("4".to_owned()));
+        assert_eq!(handle(&mut worker, "patch"), Ok("1".to_owned()));
+        assert_eq!(handle(&mut worker, "x.y.z.w"), Ok("3.4.1".to_owned()));
+
+        let handler = Version::new("", "");
+        assert_eq!(
+            handler.handle(&mut worker, &[node_string("major")]).unwrap_err().code,
+            ErrorCode::InvalidArgument
+        );
     }
 
     #[test]
     fn test_admonition() {
         let mut evaluator = Evaluator::new();
         let mut worker = Worker::new(&mut evaluator);
-        let handler = Admonition::new("note", "Note");
+        let handler = Admonition::from_config("note", &AdmonitionConfig::default());
 
         assert!(handler.handle(&mut worker, &[]).is_err());
         assert!(handler.handle(&mut worker, &[node_string("foo")]).is_ok());
+
+        let config = AdmonitionConfig {
+            icon: Some("💡".to_owned()),
+            collapsible: Some(true),
+            ..Default::default()
+        };
+        let handler = Admonition::from_config("see-also", &config);
+        assert_eq!(
+            handler.handle(&mut worker, &[node_string("foo")]),
+            Ok(concat!(
+                r#"<details class="admonition admonition-see-also" open>"#,
+                r#"<summary class="admonition-title admonition-title-see-also">"#,
+                r#"<span class="admonition-icon" aria-hidden="true">💡</span>See Also</summary>"#,
+                "foo</details>\n"
+            ).to_owned())
+        );
+
+        // Nested admonitions each close their own element
+        let inner = Admonition::from_config("warning", &AdmonitionConfig::default())
+            .handle(&mut worker, &[node_string("collapsed"), node_string("inner")])
+            .unwrap();
+        let outer = Admonition::from_config("note", &AdmonitionConfig::default())
+            .handle(&mut worker, &[node_string("Title"), node_string(&inner)])
+            .unwrap();
+        assert_eq!(
+            outer,
+            concat!(
+                r#"<div class="admonition admonition-note">"#,
+                r#"<span class="admonition-title admonition-title-note">Title</span>"#,
+                r#"<details class="admonition admonition-warning">"#,
+                r#"<summary class="admonition-title admonition-title-warning">Warning</summary>"#,
+                "inner</details>\n</div>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_admonition_config() {
+        let mut config = AdmonitionConfig {
+            title: Some("Tip".to_owned()),
+            icon: Some("!".to_owned()),
+            ..Default::default()
+        };
+        config.merge(AdmonitionConfig {
+            title: Some("Hint".to_owned()),
+            collapsed: Some(true),
+            ..Default::default()
+        });
+        assert_eq!(
+            config,
+            AdmonitionConfig {
+                title: Some("Hint".to_owned()),
+                class: None,
+                icon: Some("!".to_owned()),
+                collapsible: None,
+                collapsed: Some(true),
+            }
+        );
+
+        let admonition = Admonition::from_config("tip", &config);
+        assert_eq!(admonition.title, "Hint");
+        assert_eq!(admonition.class, "tip");
+        assert_eq!(admonition.collapse, Collapse::Closed);
     }
 
     #[test]
     fn test_concat() {
         let mut evaluator = Evaluator::new();
         let mut worker = Worker::new(&mut evaluator);
-        worker.register("version", Box::new(Version::new("3.4")));
+        worker.register("version", Box::new(Version::new("3.4", "3.4")));
         let handler = Concat;
 
         assert_eq!(handler.handle(&mut worker, &[]), Ok("".to_owned()));
@@ -832,6 +1890,24 @@ mod tests {
         );
 
         assert!(handler.handle(&mut worker, &[]).is_err());
+
+        let span = Span::new(Position::new(40, 4, 2), Position::new(52, 4, 14));
+        assert_eq!(
+            handler.handle(
+                &mut worker,
+                &[node_string("Title"), Node::new_string("foxquill.com", 0, span)]
+            ),
+            Err(Diagnostic {
+                level: Level::Error,
+                code: ErrorCode::ArgumentMismatch,
+                message: "argument 2 does not match regex `^/.*$`".to_owned(),
+                location: Some(Location {
+                    file_id: Some(0),
+                    span,
+                }),
+                notes: vec![],
+            })
+        );
         assert_eq!(
             handler.handle(
                 &mut worker,
@@ -1015,18 +2091,73 @@ mod tests {
                 Ok(r#"</section></section><h1 id="ref-a-fourth-title">A Fourth Title</h1>"#.to_owned())
             );
 
-            assert_eq!(worker.close_sections(), "</section>".to_owned());
+            // Repeated titles get distinct ids
+            let handler = Heading::new(2);
+            assert_eq!(
+                handler.handle(&mut worker, &[node_string("A Second Title")]),
+                Ok(r#"<section><h2 id="ref-a-second-title-2">A Second Title</h2>"#.to_owned())
+            );
+
+            // Links are left out of ids, which mustn't depend on placeholders
+            let title = format!(
+                r#"<a href="{}">Getting <code>Started</code></a>"#,
+                worker.get_placeholder("ref-install".to_owned(), PlaceholderAction::Path)
+            );
+            let html = handler.handle(&mut worker, &[node_string(&title)]).unwrap();
+            assert!(html.ends_with(&format!(r#"<h2 id="ref-getting-started">{}</h2>"#, title)));
+
+            // A repeated id is reported, and the first heading keeps the reference
+            assert_eq!(worker.get_error_count(), 0);
+            let html = handler
+                .handle(&mut worker, &[node_string("a-title"), node_string("Another Title")])
+                .unwrap();
+            assert!(html.ends_with(r#"<h2 id="a-title-2">Another Title</h2>"#));
+            assert_eq!(worker.get_error_count(), 1);
+
+            // Ids of other elements on the page are taken too
+            worker
+                .add_footnote(&node_string("note"), None, Some("A note".to_owned()))
+                .unwrap();
+            let html = handler
+                .handle(&mut worker, &[node_string("fn-1"), node_string("Notes")])
+                .unwrap();
+            assert!(html.ends_with(r#"<h2 id="fn-1-2">Notes</h2>"#));
+            assert_eq!(worker.get_error_count(), 2);
+
+            // ...and footnotes step around ids that headings already took
+            handler
+                .handle(&mut worker, &[node_string("fn-2"), node_string("More Notes")])
+                .unwrap();
+            handler
+                .handle(&mut worker, &[node_string("fnref-2"), node_string("Even More Notes")])
+                .unwrap();
+            let marker = worker
+                .add_footnote(&node_string("note"), None, Some("Another note".to_owned()))
+                .unwrap();
+            assert_eq!(
+                marker,
+                r##"<sup class="footnote-ref"><a href="#fn-2-2" id="fnref-2-2" role="doc-noteref">2</a></sup>"##
+            );
+            let footnotes = worker.close_footnotes();
+            assert!(footnotes.contains(r#"<li id="fn-2-2">Another note"#));
+            assert!(footnotes.contains(r##"<a href="#fnref-2-2" class="footnote-backref""##));
+            assert_eq!(worker.get_error_count(), 2);
+
+            assert_eq!(worker.close_sections(), "</section></section>".to_owned());
         }
 
+        let refdefs = evaluator.refdefs.read().unwrap();
+        assert_eq!(refdefs["ref-a-title"].title, "A Title");
+        assert!(refdefs.contains_key("ref-index#a-title-2"));
+        assert_eq!(refdefs["ref-a-title"].fragment, Some("a-title".to_owned()));
+        assert!(refdefs.contains_key("ref-index#a-title"));
         assert_eq!(
-            evaluator
-                .refdefs
-                .read()
-                .unwrap()
-                .get("ref-a-title")
-                .unwrap()
-                .title,
-            "A Title".to_owned()
+            refdefs["ref-index#ref-a-second-title-2"].fragment,
+            Some("ref-a-second-title-2".to_owned())
+        );
+        assert_eq!(
+            refdefs["ref-index#ref-getting-started"].title,
+            "Getting <code>Started</code>"
         );
     }
 
@@ -1051,6 +2182,7 @@ mod tests {
                 ),
                 Ok(String::new())
             );
+            assert_eq!(worker.get_warning_count(), 0);
         }
 
         assert_eq!(
@@ -1063,12 +2195,30 @@ mod tests {
                 .title,
             "A Title".to_owned()
         );
+
+        // Redefining a reference from another page is suspicious
+        let mut worker = Worker::new(&evaluator);
+        worker.set_slug(Slug::new("other".to_owned()));
+        let handler = RefDefDirective::new("ref");
+        assert_eq!(
+            handler.handle(
+                &mut worker,
+                &[node_string("a-title"), node_string("Another Title")]
+            ),
+            Ok(String::new())
+        );
+        assert_eq!(worker.get_warning_count(), 1);
     }
 
     #[test]
     fn test_figure() {
-        let mut evaluator = Evaluator::new();
-        let mut worker = Worker::new(&mut evaluator);
+        let root = env::temp_dir().join(format!("rocket-test-figure-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        File::create(root.join("foo.png")).unwrap();
+        File::create(root.join("fo\"o.png")).unwrap();
+
+        let evaluator = Evaluator::new_with_options(root.to_owned(), true);
+        let mut worker = Worker::new(&evaluator);
         worker.set_slug(Slug::new("index".to_owned()));
         let handler = Figure;
 
@@ -1078,12 +2228,22 @@ mod tests {
                 .handle(&mut worker, &[node_string("foo.png")])
                 .is_err()
         );
+        assert!(
+            handler
+                .handle(&mut worker, &[node_string("missing.png"), node_string("alt")])
+                .is_err()
+        );
+        assert!(
+            handler
+                .handle(&mut worker, &[node_string("../foo.png"), node_string("alt")])
+                .is_err()
+        );
         assert_eq!(
             handler.handle(
                 &mut worker,
                 &[node_string("fo\"o.png"), node_string("al\"t")]
             ),
-            Ok(r#"<img src="_static/fo&#34;o.png" alt="al&#34;t">"#.to_owned())
+            Ok(r#"<figure><img src="_static/fo&#34;o.png" alt="al&#34;t"></figure>"#.to_owned())
         );
         assert_eq!(
             handler.handle(
@@ -1094,14 +2254,185 @@ mod tests {
                     node_string("320")
                 ]
             ),
-            Ok(r#"<img src="_static/fo&#34;o.png" alt="al&#34;t" width=320px>"#.to_owned())
+            Ok(concat!(
+                r#"<figure><img src="_static/fo&#34;o.png" alt="al&#34;t" width=320px>"#,
+                "</figure>"
+            ).to_owned())
+        );
+        assert_eq!(
+            handler
+                .handle(
+                    &mut worker,
+                    &[node_string("foo.png"), node_string("alt"), node_string("wide")]
+                )
+                .unwrap_err()
+                .code,
+            ErrorCode::InvalidArgument
+        );
+        assert_eq!(
+            handler
+                .handle(
+                    &mut worker,
+                    &[
+                        node_string("foo.png"),
+                        node_string("alt"),
+                        node_children(vec![node_string("legend")]),
+                    ]
+                )
+                .unwrap_err()
+                .code,
+            ErrorCode::InvalidArgument
+        );
+
+        // Figures with a caption or an id are numbered on each page
+        let captioned = handler
+            .handle(
+                &mut worker,
+                &[
+                    node_string("foo.png"),
+                    node_string("alt"),
+                    node_children(vec![
+                        node_string("caption"),
+                        node_string("A"),
+                        node_string("fox"),
+                    ]),
+                ],
+            )
+            .unwrap();
+        let with_id = handler
+            .handle(
+                &mut worker,
+                &[
+                    node_string("foo.png"),
+                    node_string("alt"),
+                    node_string("320"),
+                    node_string("id=fox"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            link(&evaluator, "index", &captioned),
+            concat!(
+                r#"<figure><img src="_static/foo.png" alt="alt"><figcaption>"#,
+                r#"<span class="caption-number">Figure 1</span> A fox</figcaption></figure>"#
+            )
+        );
+        assert_eq!(
+            link(&evaluator, "index", &with_id),
+            concat!(
+                r#"<figure id="fox"><img src="_static/foo.png" alt="alt" width=320px>"#,
+                r#"<figcaption><span class="caption-number">Figure 2</span></figcaption></figure>"#
+            )
         );
 
+        let refdef = evaluator.refdefs.read().unwrap()["ref-fox"].clone();
+        assert_eq!(refdef.fragment, Some("fox".to_owned()));
+        let link_to_fox = format!(
+            "{}|{}",
+            worker.get_placeholder("ref-fox".to_owned(), PlaceholderAction::Title),
+            worker.get_placeholder("ref-fox".to_owned(), PlaceholderAction::Path)
+        );
+        assert_eq!(link(&evaluator, "reference/api", &link_to_fox), "Figure 2|../../#fox");
+
         worker.set_slug(Slug::new("reference/directives".to_owned()));
         assert_eq!(
-            handler.handle(&mut worker, &[node_string("foo.png"), node_string("foo")]),
-            Ok(r#"<img src="../../_static/foo.png" alt="foo">"#.to_owned())
+            handler.handle(&mut worker, &[node_string("/foo.png"), node_string("foo")]),
+            Ok(r#"<figure><img src="../../_static/foo.png" alt="foo"></figure>"#.to_owned())
+        );
+        assert_eq!(
+            worker.page_assets,
+            vec![
+                Asset {
+                    source_path: root.join("foo.png"),
+                    output_path: PathBuf::from("foo.png"),
+                },
+            ]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_table() {
+        let evaluator = Evaluator::new();
+        let mut worker = Worker::new(&evaluator);
+        worker.set_slug(Slug::new("index".to_owned()));
+        worker.register("concat", Box::new(Concat));
+        let handler = Table;
+
+        assert_eq!(
+            handler.handle(&mut worker, &[]),
+            Ok("<table><tbody></tbody></table>".to_owned())
+        );
+        assert!(handler.handle(&mut worker, &[node_string("row")]).is_err());
+        assert!(
+            handler
+                .handle(&mut worker, &[node_children(vec![node_string("cell")])])
+                .is_err()
+        );
+        assert!(
+            handler
+                .handle(
+                    &mut worker,
+                    &[node_children(vec![node_string("align"), node_string("middle")])]
+                )
+                .is_err()
+        );
+
+        let table = handler
+            .handle(
+                &mut worker,
+                &[
+                    node_children(vec![
+                        node_string("row"),
+                        node_string("Linux"),
+                        node_children(vec![
+                            node_string("concat"),
+                            node_string("Y"),
+                            node_string("es"),
+                        ]),
+                    ]),
+                    node_children(vec![
+                        node_string("header"),
+                        node_string("Platform"),
+                        node_string("Supported"),
+                    ]),
+                    node_children(vec![
+                        node_string("align"),
+                        node_string("default"),
+                        node_string("center"),
+                    ]),
+                    node_children(vec![
+                        node_string("caption"),
+                        node_string("Supported"),
+                        node_string("Platforms"),
+                    ]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            link(&evaluator, "index", &table),
+            concat!(
+                r#"<table><caption><span class="caption-number">Table 1</span> "#,
+                "Supported Platforms</caption>",
+                "<thead><tr><th>Platform</th>",
+                r#"<th style="text-align:center">Supported</th></tr></thead>"#,
+                "<tbody><tr><td>Linux</td>",
+                r#"<td style="text-align:center">Yes</td></tr></tbody></table>"#
+            )
+        );
+
+        let table = handler
+            .handle(&mut worker, &[node_string("id=platforms")])
+            .unwrap();
+        assert_eq!(
+            link(&evaluator, "index", &table),
+            concat!(
+                r#"<table id="platforms"><caption><span class="caption-number">Table 2</span>"#,
+                "</caption><tbody></tbody></table>"
+            )
         );
+        assert!(evaluator.refdefs.read().unwrap().contains_key("ref-platforms"));
     }
 
     #[test]
@@ -1163,4 +2494,52 @@ mod tests {
             Ok(r#"<a href="https://foxquill.com">foobar baz</a>"#.to_owned())
         );
     }
+
+    #[test]
+    fn test_footnote() {
+        let evaluator = Evaluator::new();
+        let mut worker = Worker::new(&evaluator);
+        worker.set_slug(Slug::new("index".to_owned()));
+
+        assert!(Footnote.handle(&mut worker, &[]).is_err());
+        assert_eq!(
+            Footnote.handle(&mut worker, &[node_string("First")]),
+            Ok(concat!(
+                r##"<sup class="footnote-ref">"##,
+                r##"<a href="#fn-1" id="fnref-1" role="doc-noteref">1</a></sup>"##
+            ).to_owned())
+        );
+
+        // Named footnotes may be referred to before and after their definition
+        let marker = FootnoteRef.handle(&mut worker, &[node_string("caveat")]).unwrap();
+        assert!(marker.contains(r##"<a href="#fn-2" id="fnref-2" "##));
+        let marker = Footnote
+            .handle(&mut worker, &[node_string("caveat"), node_string("Second")])
+            .unwrap();
+        assert!(marker.contains(r##"<a href="#fn-2" id="fnref-2-2" "##));
+        assert!(FootnoteRef.handle(&mut worker, &[node_string("caveat")]).is_ok());
+        assert_eq!(
+            Footnote
+                .handle(&mut worker, &[node_string("caveat"), node_string("Again")])
+                .unwrap_err()
+                .code,
+            ErrorCode::DuplicateReference
+        );
+
+        assert!(FootnoteRef.handle(&mut worker, &[node_string("missing")]).is_ok());
+
+        let footnotes = worker.close_footnotes();
+        assert!(footnotes.starts_with(r#"<section class="footnotes" role="doc-endnotes"><ol>"#));
+        assert!(footnotes.contains(concat!(
+            r##"<li id="fn-1">First "##,
+            r##"<a href="#fnref-1" class="footnote-backref" role="doc-backlink">↩</a></li>"##
+        )));
+        assert!(footnotes.contains(r##"<li id="fn-2">Second <a href="#fnref-2" "##));
+        assert!(footnotes.contains(r##"<a href="#fnref-2-3" class="footnote-backref" "##));
+        assert!(footnotes.contains(r#"<li id="fn-3"> <a"#));
+        assert_eq!(evaluator.reporter.counts().errors, 1);
+
+        // Footnotes are numbered per page
+        assert_eq!(worker.close_footnotes(), "");
+    }
 }
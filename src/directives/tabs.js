@@ -0,0 +1,78 @@
+// Keeps sets of tabs written with the tabs directive in sync. Selecting a tab in a
+// set with a data-tab-group attribute selects the tab with the same title in every
+// other set in that group, and is remembered across pages. Without this script the
+// tabs still work, but independently.
+(function() {
+    'use strict';
+
+    var STORAGE_PREFIX = 'rocket-tabs-';
+
+    function title(input) {
+        var label = input.nextElementSibling;
+        return label ? label.textContent.trim() : '';
+    }
+
+    function select(group, selectedTitle) {
+        var sets = document.querySelectorAll('.tabs[data-tab-group]');
+        Array.prototype.forEach.call(sets, function(set) {
+            if (set.getAttribute('data-tab-group') !== group) {
+                return;
+            }
+
+            var inputs = set.querySelectorAll(':scope > .tabs__input');
+            Array.prototype.forEach.call(inputs, function(input) {
+                if (title(input) === selectedTitle) {
+                    input.checked = true;
+                }
+            });
+        });
+    }
+
+    function remember(group, selectedTitle) {
+        try {
+            window.localStorage.setItem(STORAGE_PREFIX + group, selectedTitle);
+        } catch (err) {
+            // Storage may be unavailable, such as for file: URLs
+        }
+    }
+
+    function recall(group) {
+        try {
+            return window.localStorage.getItem(STORAGE_PREFIX + group);
+        } catch (err) {
+            return null;
+        }
+    }
+
+    function init() {
+        var groups = {};
+        var sets = document.querySelectorAll('.tabs[data-tab-group]');
+        Array.prototype.forEach.call(sets, function(set) {
+            var group = set.getAttribute('data-tab-group');
+            groups[group] = true;
+
+            set.addEventListener('change', function(event) {
+                var input = event.target;
+                if (!input.classList.contains('tabs__input') || !input.checked) {
+                    return;
+                }
+
+                select(group, title(input));
+                remember(group, title(input));
+            });
+        });
+
+        Object.keys(groups).forEach(function(group) {
+            var remembered = recall(group);
+            if (remembered !== null) {
+                select(group, remembered);
+            }
+        });
+    }
+
+    if (document.readyState === 'loading') {
+        document.addEventListener('DOMContentLoaded', init);
+    } else {
+        init();
+    }
+})();
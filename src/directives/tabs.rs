@@ -0,0 +1,177 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use diagnostic::{Diagnostic, ErrorCode};
+use directives::{escape_string, DirectiveHandler};
+use evaluator::Worker;
+use parse::{Node, NodeValue};
+
+pub const SCRIPT_PATH: &str = "_static/tabs.js";
+const SCRIPT: &str = include_str!("tabs.js");
+
+/// Write the script that keeps tabs in the same group in sync into the output
+/// directory.
+pub fn write_script(output: &Path) -> io::Result<()> {
+    let script_path = output.join(SCRIPT_PATH);
+    if let Some(parent) = script_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    File::create(script_path)?.write_all(SCRIPT.as_bytes())
+}
+
+/// A set of tabs, each given as (:tab <title> <body>). The tabs are radio buttons so
+/// that they work without JavaScript, and a script selects the same tab in every set
+/// sharing a group=<name> option.
+pub struct Tabs;
+
+impl DirectiveHandler for Tabs {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        let mut group = None;
+        let mut tabs = Vec::with_capacity(args.len());
+
+        for node in args {
+            let invalid_tab = || {
+                Diagnostic::error(ErrorCode::InvalidArgument, "expected a tab title and body")
+                    .at(node)
+            };
+
+            match node.value {
+                NodeValue::Owned(ref s) if s.starts_with("group=") && tabs.is_empty() => {
+                    group = Some(s["group=".len()..].to_owned());
+                }
+                NodeValue::Children(ref children)
+                    if children.len() == 3 && worker.evaluate(&children[0]) == "tab" =>
+                {
+                    tabs.push((worker.evaluate(&children[1]), worker.evaluate(&children[2])));
+                }
+                _ => return Err(invalid_tab()),
+            }
+        }
+
+        if tabs.is_empty() {
+            return Err(Diagnostic::missing_argument("tab"));
+        }
+
+        let set = worker.next_tab_set();
+        let mut result = String::from(r#"<div class="tabs""#);
+        if let Some(ref group) = group {
+            result.push_str(&format!(r#" data-tab-group="{}""#, escape_string(group)));
+        }
+        result.push('>');
+
+        for (i, (title, body)) in tabs.iter().enumerate() {
+            let id = format!("tabs-{}-{}", set, i + 1);
+            result.push_str(&format!(
+                concat!(
+                    r#"<input type="radio" class="tabs__input" name="tabs-{}" id="{}"{}>"#,
+                    r#"<label class="tabs__label" id="{}-label" for="{}">{}</label>"#,
+                    r#"<div class="tabs__panel" role="region" aria-labelledby="{}-label">"#,
+                    "{}</div>"
+                ),
+                set,
+                id,
+                if i == 0 { " checked" } else { "" },
+                id,
+                id,
+                title,
+                id,
+                body
+            ));
+        }
+        result.push_str("</div>");
+
+        if set == 1 {
+            result.push_str(&format!(
+                r#"<script src="{}" defer></script>"#,
+                worker.path_to(SCRIPT_PATH)
+            ));
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evaluator::Evaluator;
+    use lex::Span;
+    use page::Slug;
+
+    fn node_string(s: &str) -> Node {
+        Node::new_string(s, 0, Span::unknown())
+    }
+
+    fn tab(title: &str, body: &str) -> Node {
+        Node::new_children(
+            vec![node_string("tab"), node_string(title), node_string(body)],
+            0,
+            Span::unknown(),
+        )
+    }
+
+    #[test]
+    fn test_tabs() {
+        let evaluator = Evaluator::new();
+        let mut worker = Worker::new(&evaluator);
+        worker.set_slug(Slug::new("install".to_owned()));
+        let handler = Tabs;
+
+        assert_eq!(
+            handler.handle(&mut worker, &[]).unwrap_err().code,
+            ErrorCode::MissingArgument
+        );
+        assert_eq!(
+            handler
+                .handle(&mut worker, &[node_string("linux")])
+                .unwrap_err()
+                .code,
+            ErrorCode::InvalidArgument
+        );
+        let note = Node::new_children(
+            vec![node_string("note"), node_string("a"), node_string("b")],
+            0,
+            Span::unknown(),
+        );
+        assert_eq!(
+            handler.handle(&mut worker, &[note]).unwrap_err().code,
+            ErrorCode::InvalidArgument
+        );
+
+        assert_eq!(
+            handler.handle(
+                &mut worker,
+                &[node_string("group=os"), tab("Linux", "apt"), tab("macOS", "brew")]
+            ),
+            Ok(concat!(
+                r#"<div class="tabs" data-tab-group="os">"#,
+                r#"<input type="radio" class="tabs__input" name="tabs-1" id="tabs-1-1" checked>"#,
+                r#"<label class="tabs__label" id="tabs-1-1-label" for="tabs-1-1">Linux</label>"#,
+                r#"<div class="tabs__panel" role="region" aria-labelledby="tabs-1-1-label">"#,
+                "apt</div>",
+                r#"<input type="radio" class="tabs__input" name="tabs-1" id="tabs-1-2">"#,
+                r#"<label class="tabs__label" id="tabs-1-2-label" for="tabs-1-2">macOS</label>"#,
+                r#"<div class="tabs__panel" role="region" aria-labelledby="tabs-1-2-label">"#,
+                "brew</div></div>",
+                r#"<script src="../_static/tabs.js" defer></script>"#
+            ).to_owned())
+        );
+
+        // The script is only needed once per page
+        let html = handler
+            .handle(&mut worker, &[tab("Windows", "choco")])
+            .unwrap();
+        assert!(html.starts_with(
+            r#"<div class="tabs"><input type="radio" class="tabs__input" name="tabs-2""#
+        ));
+        assert!(!html.contains("<script"));
+
+        worker.set_slug(Slug::new("index".to_owned()));
+        let html = handler
+            .handle(&mut worker, &[tab("Windows", "choco")])
+            .unwrap();
+        assert!(html.contains(r#"name="tabs-1""#));
+        assert!(html.ends_with(r#"<script src="_static/tabs.js" defer></script>"#));
+    }
+}
@@ -0,0 +1,400 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use diagnostic::{Diagnostic, ErrorCode};
+use directives::{escape_string, release_components, DirectiveHandler, Version};
+use evaluator::Worker;
+use page::Slug;
+use parse::Node;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Change {
+    Added,
+    Changed,
+    Deprecated,
+}
+
+impl Change {
+    pub fn name(self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Changed => "changed",
+            Change::Deprecated => "deprecated",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Change::Added => "New in version",
+            Change::Changed => "Changed in version",
+            Change::Deprecated => "Deprecated since version",
+        }
+    }
+
+    fn heading(self) -> &'static str {
+        match self {
+            Change::Added => "Added",
+            Change::Changed => "Changed",
+            Change::Deprecated => "Deprecated",
+        }
+    }
+}
+
+/// Content that a page marks as added, changed, or deprecated in some version.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VersionNote {
+    pub change: Change,
+    pub version: String,
+    pub slug: Slug,
+
+    /// The id of the note's callout within its page
+    pub id: String,
+    pub summary: String,
+}
+
+/// Parse a version such as "3.2" into its numeric components.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let components = release_components(version);
+    if components.is_empty() {
+        return None;
+    }
+
+    components.iter().map(|component| component.parse().ok()).collect()
+}
+
+/// Compare versions component by component, treating missing components as 0 so
+/// that "3.2" and "3.2.0" are equal.
+fn compare_versions(a: &[u64], b: &[u64]) -> Ordering {
+    for i in 0..a.len().max(b.len()) {
+        let ordering = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// The number of breaking releases from old to new. Before 1.0, each minor release
+/// counts as breaking.
+fn breaking_releases_between(old: &[u64], new: &[u64]) -> u64 {
+    let component = |version: &[u64], i: usize| version.get(i).cloned().unwrap_or(0);
+    if component(old, 0) == 0 && component(new, 0) == 0 {
+        component(new, 1).saturating_sub(component(old, 1))
+    } else {
+        component(new, 0).saturating_sub(component(old, 0))
+    }
+}
+
+/// Marks content as added, changed, or deprecated in a version, which is checked
+/// against the project's release.
+pub struct VersionChange {
+    change: Change,
+    project: Version,
+
+    /// How many breaking releases may pass before deprecated content is reported as
+    /// due for removal, or 0 to never report it
+    deprecation_window: u64,
+}
+
+impl VersionChange {
+    pub fn new(change: Change, project: Version, deprecation_window: u64) -> Self {
+        VersionChange {
+            change,
+            project,
+            deprecation_window,
+        }
+    }
+
+    /// Warn about a version that the project hasn't reached, or a deprecation that
+    /// has outlived the deprecation window.
+    fn check(&self, worker: &mut Worker, node: &Node, version: &str, components: &[u64]) {
+        let release = match parse_version(&self.project.release) {
+            Some(release) => release,
+            None => return,
+        };
+
+        if compare_versions(components, &release) == Ordering::Greater {
+            worker.report(&Diagnostic::warning(
+                ErrorCode::UnreleasedVersion,
+                format!(
+                    "version {} is newer than the project's release, {}",
+                    version,
+                    self.project.release
+                ),
+            ).at(node)
+                .with_note("set the project's release in config.toml"));
+            return;
+        }
+
+        let age = breaking_releases_between(components, &release);
+        if self.change == Change::Deprecated && self.deprecation_window > 0
+            && age >= self.deprecation_window
+        {
+            worker.report(&Diagnostic::warning(
+                ErrorCode::OutdatedDeprecation,
+                format!("content deprecated in version {} is due for removal", version),
+            ).at(node)
+                .with_note(format!(
+                    "the project's release, {}, is {} breaking release{} later",
+                    self.project.release,
+                    age,
+                    if age == 1 { "" } else { "s" }
+                )));
+        }
+    }
+}
+
+impl DirectiveHandler for VersionChange {
+    fn handle(&self, worker: &mut Worker, args: &[Node]) -> Result<String, Diagnostic> {
+        let body = match args.len() {
+            0 => return Err(Diagnostic::missing_argument("version")),
+            1 => String::new(),
+            2 => worker.evaluate(&args[1]),
+            _ => return Err(Diagnostic::too_many_arguments(2)),
+        };
+
+        let version = worker.evaluate(&args[0]);
+        let components = parse_version(&version).ok_or_else(|| {
+            Diagnostic::error(
+                ErrorCode::InvalidArgument,
+                format!("invalid version '{}'", version),
+            ).at(&args[0])
+                .with_note("expected a version such as '3.2'")
+        })?;
+
+        self.check(worker, &args[0], &version, &components);
+        let id = worker.add_version_note(self.change, &version, &body);
+
+        let mut title = format!("{} {}", self.change.label(), escape_string(&version));
+        let body = if body.is_empty() {
+            body
+        } else {
+            title.push(':');
+            format!(" {}", body)
+        };
+
+        Ok(format!(
+            concat!(
+                r#"<div class="version-note version-note-{}" id="{}">"#,
+                r#"<span class="version-note-title">{}</span>{}</div>"#,
+                "\n"
+            ),
+            self.change.name(),
+            escape_string(&id),
+            title,
+            body
+        ))
+    }
+}
+
+/// A generated page listing what changed in one version.
+pub struct WhatsNewPage {
+    pub slug: Slug,
+    pub title: String,
+    pub body: String,
+}
+
+/// Generate a page under prefix for each version that has notes, linking to each
+/// note from the page that it appears on. titles gives the title of each page.
+pub fn whats_new_pages(
+    notes: &[VersionNote],
+    titles: &HashMap<Slug, String>,
+    prefix: &str,
+    pretty_url: bool,
+) -> Vec<WhatsNewPage> {
+    let mut versions: Vec<(Vec<u64>, &str)> = notes
+        .iter()
+        .filter_map(|note| {
+            parse_version(&note.version).map(|parsed| (parsed, note.version.as_str()))
+        })
+        .collect();
+    versions.sort_by(|a, b| compare_versions(&b.0, &a.0).then_with(|| a.1.cmp(b.1)));
+    versions.dedup_by(|a, b| compare_versions(&a.0, &b.0) == Ordering::Equal);
+
+    versions
+        .iter()
+        .map(|&(ref parsed, version)| {
+            let slug = Slug::new(format!("{}/{}", prefix, version));
+            let title = format!("What's New in {}", escape_string(version));
+
+            let mut notes: Vec<_> = notes
+                .iter()
+                .filter(|note| {
+                    parse_version(&note.version)
+                        .map(|other| compare_versions(&other, parsed) == Ordering::Equal)
+                        .unwrap_or(false)
+                })
+                .collect();
+            notes.sort_by(|a, b| {
+                (a.change, a.slug.as_ref(), &a.id).cmp(&(b.change, b.slug.as_ref(), &b.id))
+            });
+
+            let mut body = format!("<section><h1>{}</h1>\n", title);
+            for change in &[Change::Added, Change::Changed, Change::Deprecated] {
+                let mut items = notes.iter().filter(|note| note.change == *change).peekable();
+                if items.peek().is_none() {
+                    continue;
+                }
+
+                body.push_str(&format!("<h2>{}</h2>\n<ul>\n", change.heading()));
+                for note in items {
+                    let url = slug.path_to(&note.slug.url(pretty_url), pretty_url);
+                    let page_title = titles
+                        .get(&note.slug)
+                        .map(|title| title.as_str())
+                        .unwrap_or_else(|| note.slug.as_ref());
+                    body.push_str(&format!(
+                        r#"<li><a href="{}#{}">{}</a>"#,
+                        url,
+                        escape_string(&note.id),
+                        page_title
+                    ));
+                    if !note.summary.is_empty() {
+                        body.push_str(": ");
+                        body.push_str(&note.summary);
+                    }
+                    body.push_str("</li>\n");
+                }
+                body.push_str("</ul>\n");
+            }
+            body.push_str("</section>");
+
+            WhatsNewPage { slug, title, body }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evaluator::Evaluator;
+    use lex::Span;
+
+    fn node_string(s: &str) -> Node {
+        Node::new_string(s, 0, Span::unknown())
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        let v = |s: &str| parse_version(s).unwrap();
+        assert_eq!(compare_versions(&v("3.2"), &v("3.2.0-rc1")), Ordering::Equal);
+        assert_eq!(compare_versions(&v("3.10"), &v("3.9")), Ordering::Greater);
+        assert!(parse_version("three").is_none());
+        assert!(parse_version("").is_none());
+
+        assert_eq!(breaking_releases_between(&v("1.4"), &v("3.0")), 2);
+        assert_eq!(breaking_releases_between(&v("0.4"), &v("0.6.1")), 2);
+        assert_eq!(breaking_releases_between(&v("0.4"), &v("1.0")), 1);
+        assert_eq!(breaking_releases_between(&v("2.1"), &v("2.8")), 0);
+    }
+
+    #[test]
+    fn test_version_change() {
+        let evaluator = Evaluator::new();
+        let mut worker = Worker::new(&evaluator);
+        worker.set_slug(Slug::new("api".to_owned()));
+        let added = VersionChange::new(Change::Added, Version::new("3.2", "3.2.1"), 1);
+        let deprecated =
+            VersionChange::new(Change::Deprecated, Version::new("3.2", "3.2.1"), 1);
+
+        assert!(added.handle(&mut worker, &[]).is_err());
+        assert_eq!(
+            added
+                .handle(&mut worker, &[node_string("next")])
+                .unwrap_err()
+                .code,
+            ErrorCode::InvalidArgument
+        );
+
+        assert_eq!(
+            added.handle(&mut worker, &[node_string("3.1")]),
+            Ok(concat!(
+                r#"<div class="version-note version-note-added" id="version-added-3.1">"#,
+                r#"<span class="version-note-title">New in version 3.1</span></div>"#,
+                "\n"
+            ).to_owned())
+        );
+        assert_eq!(
+            added.handle(&mut worker, &[node_string("3.1"), node_string("The flag.")]),
+            Ok(concat!(
+                r#"<div class="version-note version-note-added" id="version-added-3.1-2">"#,
+                r#"<span class="version-note-title">New in version 3.1:</span> The flag.</div>"#,
+                "\n"
+            ).to_owned())
+        );
+        assert_eq!(worker.get_warning_count(), 0);
+
+        // Within the deprecation window
+        assert!(deprecated.handle(&mut worker, &[node_string("3.0")]).is_ok());
+        assert_eq!(worker.get_warning_count(), 0);
+
+        assert!(deprecated.handle(&mut worker, &[node_string("2.5")]).is_ok());
+        assert_eq!(worker.get_warning_count(), 1);
+        assert!(added.handle(&mut worker, &[node_string("3.3")]).is_ok());
+        assert_eq!(worker.get_warning_count(), 2);
+
+        assert_eq!(worker.page_version_notes.len(), 5);
+        assert_eq!(
+            worker.page_version_notes[1],
+            VersionNote {
+                change: Change::Added,
+                version: "3.1".to_owned(),
+                slug: Slug::new("api".to_owned()),
+                id: "version-added-3.1-2".to_owned(),
+                summary: "The flag.".to_owned(),
+            }
+        );
+        assert_eq!(evaluator.version_notes.read().unwrap().len(), 5);
+
+        let never = VersionChange::new(Change::Deprecated, Version::new("3.2", "3.2.1"), 0);
+        assert!(never.handle(&mut worker, &[node_string("1.0")]).is_ok());
+        assert_eq!(worker.get_warning_count(), 2);
+    }
+
+    #[test]
+    fn test_whats_new() {
+        let note = |change, version: &str, slug: &str, summary: &str| VersionNote {
+            change,
+            version: version.to_owned(),
+            slug: Slug::new(slug.to_owned()),
+            id: format!("version-{}-{}", change.name(), version),
+            summary: summary.to_owned(),
+        };
+
+        let notes = vec![
+            note(Change::Deprecated, "3.2", "reference/api", "Use run."),
+            note(Change::Added, "3.10", "index", ""),
+            note(Change::Added, "3.2.0", "reference/api", "The run function."),
+        ];
+        let mut titles = HashMap::new();
+        titles.insert(Slug::new("reference/api".to_owned()), "API".to_owned());
+
+        let pages = whats_new_pages(&notes, &titles, "whats-new", true);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].slug, Slug::new("whats-new/3.10".to_owned()));
+        assert_eq!(pages[0].title, "What's New in 3.10");
+        assert_eq!(
+            pages[0].body,
+            concat!(
+                "<section><h1>What's New in 3.10</h1>\n",
+                "<h2>Added</h2>\n<ul>\n",
+                r#"<li><a href="../../#version-added-3.10">index</a></li>"#,
+                "\n</ul>\n</section>"
+            )
+        );
+
+        assert_eq!(pages[1].slug, Slug::new("whats-new/3.2".to_owned()));
+        assert_eq!(
+            pages[1].body,
+            concat!(
+                "<section><h1>What's New in 3.2</h1>\n",
+                "<h2>Added</h2>\n<ul>\n",
+                r#"<li><a href="../../reference/api#version-added-3.2.0">API</a>: The run function.</li>"#,
+                "\n</ul>\n",
+                "<h2>Deprecated</h2>\n<ul>\n",
+                r#"<li><a href="../../reference/api#version-deprecated-3.2">API</a>: Use run.</li>"#,
+                "\n</ul>\n</section>"
+            )
+        );
+    }
+}
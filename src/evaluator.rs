@@ -1,28 +1,42 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::marker::Sync;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, RwLock};
-use log;
 use serde_json;
 use rand;
 use rand::Rng;
-use regex::{Captures, Regex};
+use regex::{self, Captures, Regex};
+use diagnostic::{self, Diagnostic, ErrorCode, Level, Reporter};
 use directives;
+use directives::versions::{Change, VersionNote};
 use highlighter::{self, SyntaxHighlighter};
-use page::{Page, Slug};
-use parse::{Node, NodeValue, Parser};
-use toctree::TocTree;
+use lex::Span;
+use numbering::{Counter, Numbering};
+use page::{Asset, Page, Slug};
+use parse::{FileID, Node, NodeValue, Parser};
+use toctree::{EntrySource, TocTree};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum PlaceholderAction {
     Path,
     Title,
+
+    /// The label of the nth use of a counter on the page being linked
+    Number(Counter, usize),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RefDef {
     pub title: String,
     pub slug: Slug,
+
+    /// The id of the element to link to within the page, if any
+    pub fragment: Option<String>,
+
+    /// If set, the title is replaced with this label once numbering is known
+    pub number: Option<(Counter, usize)>,
 }
 
 impl RefDef {
@@ -30,8 +44,44 @@ impl RefDef {
         RefDef {
             title: title.to_owned(),
             slug: slug.to_owned(),
+            fragment: None,
+            number: None,
         }
     }
+
+    pub fn with_fragment<S: Into<String>>(mut self, fragment: S) -> Self {
+        self.fragment = Some(fragment.into());
+        self
+    }
+
+    pub fn with_number(mut self, counter: Counter, n: usize) -> Self {
+        self.number = Some((counter, n));
+        self
+    }
+}
+
+/// The id of a footnote's nth marker. The first marker is the one that most readers
+/// will have come from, so it gets the simplest id.
+fn footnote_marker_id(number: usize, marker: usize) -> String {
+    if marker == 1 {
+        format!("fnref-{}", number)
+    } else {
+        format!("fnref-{}-{}", number, marker)
+    }
+}
+
+/// Generate a random hexadecimal string suitable for marking placeholders.
+pub fn random_placeholder_prefix() -> String {
+    let hex_chars = b"0123456789abcdef";
+    let mut rnd_buf = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut rnd_buf);
+    let mut placeholder_prefix = String::with_capacity(32);
+    for c in &rnd_buf {
+        placeholder_prefix.push(hex_chars[(c >> 4) as usize] as char);
+        placeholder_prefix.push(hex_chars[(c & 15) as usize] as char);
+    }
+
+    placeholder_prefix
 }
 
 pub enum StoredValue {
@@ -41,44 +91,52 @@ pub enum StoredValue {
 
 pub struct Evaluator {
     pub root_path: PathBuf,
+
+    /// Whether pages are written as slug/index.html rather than slug.html
+    pub pretty_url: bool,
+
+    /// The tags that this build was given, which the only directive tests
+    pub tags: HashSet<String>,
     prelude_ctx: HashMap<String, Arc<StoredValue>>,
     pub refdefs: RwLock<HashMap<String, RefDef>>,
     pub toctree: RwLock<TocTree>,
+    pub version_notes: RwLock<Vec<VersionNote>>,
+    pub numbering: RwLock<Numbering>,
 
     placeholder_pattern: Regex,
     placeholder_prefix: String,
     pub pending_links: RwLock<Vec<(PlaceholderAction, String)>>,
+
+    pub reporter: Reporter,
 }
 
 impl Evaluator {
     #[allow(dead_code)]
     pub fn new() -> Self {
-        Self::new_with_options(PathBuf::new())
+        Self::new_with_options(PathBuf::new(), true)
     }
 
-    pub fn new_with_options(root_path: PathBuf) -> Self {
-        let hex_chars = b"0123456789abcdef";
-        let mut rnd_buf = [0u8; 16];
-        rand::thread_rng().fill_bytes(&mut rnd_buf);
-        let mut placeholder_prefix = String::with_capacity(32);
-        for c in &rnd_buf {
-            placeholder_prefix.push(hex_chars[(c >> 4) as usize] as char);
-            placeholder_prefix.push(hex_chars[(c & 15) as usize] as char);
-        }
-
+    pub fn new_with_options(root_path: PathBuf, pretty_url: bool) -> Self {
+        let placeholder_prefix = random_placeholder_prefix();
         let pattern_text = format!(r"%{}-(\d+)%", &placeholder_prefix);
         let placeholder_pattern =
             Regex::new(&pattern_text).expect("Failed to compile linker pattern");
 
         Evaluator {
             root_path,
+            pretty_url,
+            tags: HashSet::new(),
             prelude_ctx: HashMap::new(),
             refdefs: RwLock::new(HashMap::new()),
-            toctree: RwLock::new(TocTree::new(Slug::new("index".to_owned()), true)),
+            toctree: RwLock::new(TocTree::new(Slug::new("index".to_owned()), pretty_url)),
+            version_notes: RwLock::new(vec![]),
+            numbering: RwLock::new(Numbering::default()),
 
             placeholder_pattern,
             placeholder_prefix,
             pending_links: RwLock::new(vec![]),
+
+            reporter: Reporter::default(),
         }
     }
 
@@ -91,7 +149,27 @@ impl Evaluator {
             .insert(name.into(), Arc::new(StoredValue::Directive(handler)));
     }
 
-    pub fn substitute(&self, page: &Page) -> Result<String, ()> {
+    /// Whether a directive or variable with the given name is registered.
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.prelude_ctx.contains_key(name)
+    }
+
+    /// Register a project variable that evaluates to value. Returns false without
+    /// registering anything if name is already taken by a directive.
+    pub fn register_variable(&mut self, name: &str, value: &str) -> bool {
+        if self.is_defined(name) {
+            return false;
+        }
+
+        let node = Node::new_string(value, 0, Span::unknown());
+        self.prelude_ctx
+            .insert(name.to_owned(), Arc::new(StoredValue::Node(node)));
+        true
+    }
+
+    /// Resolve the placeholders in a page's body, reporting unknown references.
+    pub fn substitute(&self, page: &Page) -> String {
+        let mut unknown_refids = HashSet::new();
         let result = self.placeholder_pattern
             .replace_all(&page.body, |captures: &Captures| {
                 let ref_number = str::parse::<u64>(&captures[1]).expect("Failed to parse refid");
@@ -99,28 +177,127 @@ impl Evaluator {
                 let r2 = self.refdefs.read().unwrap();
                 let &(ref action, ref refid) =
                     r1.get(ref_number as usize).expect("Missing ref number");
+                if let PlaceholderAction::Number(counter, n) = *action {
+                    return self.numbering.read().unwrap().format(&page.slug, counter, n);
+                }
+
                 let refdef = match r2.get(refid) {
                     Some(r) => r,
                     None => {
-                        error!(
-                            "Unknown reference '{}' used in page {}",
-                            refid,
-                            page.source_path.to_string_lossy()
+                        // A ref directive often yields both a title and a path placeholder
+                        if !unknown_refids.insert(refid.to_owned()) {
+                            return "".to_owned();
+                        }
+
+                        let diagnostic = Diagnostic::error(
+                            ErrorCode::UnknownReference,
+                            format!("unknown reference '{}'", refid),
                         );
+                        let names = r2.keys().map(|name| name.as_ref());
+                        let diagnostic = match diagnostic::closest_match(refid, names) {
+                            Some(name) => diagnostic.with_note(format!("did you mean '{}'?", name)),
+                            None => diagnostic,
+                        };
+
+                        self.reporter.report(&diagnostic, Some(&page.source_path));
                         return "".to_owned();
                     }
                 };
 
                 match *action {
-                    PlaceholderAction::Path => page.slug.path_to(refdef.slug.as_ref(), true),
-                    PlaceholderAction::Title => refdef.title.to_owned(),
+                    PlaceholderAction::Path => {
+                        let path = page.slug
+                            .path_to(&refdef.slug.url(self.pretty_url), self.pretty_url);
+                        match refdef.fragment {
+                            Some(ref fragment) => format!("{}#{}", path, fragment),
+                            None => path,
+                        }
+                    }
+                    PlaceholderAction::Title => match refdef.number {
+                        Some((counter, n)) => {
+                            self.numbering.read().unwrap().format(&refdef.slug, counter, n)
+                        }
+                        None => refdef.title.to_owned(),
+                    },
+                    PlaceholderAction::Number(..) => unreachable!(),
                 }
             });
 
+        result.into_owned()
+    }
+
+    /// Rewrite this evaluator's placeholders in body into placeholders with the given
+    /// prefix, numbered by their position in the returned list of links.
+    pub fn extract_links(
+        &self,
+        body: &str,
+        prefix: &str,
+    ) -> (String, Vec<(PlaceholderAction, String)>) {
+        let pending_links = self.pending_links.read().unwrap();
+        let mut links = vec![];
+        let result = self.placeholder_pattern
+            .replace_all(body, |captures: &Captures| {
+                let ref_number = str::parse::<u64>(&captures[1]).expect("Failed to parse refid");
+                let (action, refid) = pending_links
+                    .get(ref_number as usize)
+                    .expect("Missing ref number");
+                links.push((action.clone(), refid.to_owned()));
+                format!("%{}-{}%", prefix, links.len() - 1)
+            });
+
+        (result.into_owned(), links)
+    }
+
+    /// The inverse of extract_links: register each link as pending with this evaluator,
+    /// and rewrite body to use this evaluator's placeholders.
+    pub fn restore_links(
+        &self,
+        body: &str,
+        prefix: &str,
+        links: &[(PlaceholderAction, String)],
+    ) -> Result<String, ()> {
+        let pattern_text = format!(r"%{}-(\d+)%", regex::escape(prefix));
+        let pattern = Regex::new(&pattern_text).or(Err(()))?;
+        let mut pending_links = self.pending_links.write().unwrap();
+        let mut missing_link = false;
+        let result = pattern.replace_all(body, |captures: &Captures| {
+            let link = str::parse::<usize>(&captures[1])
+                .ok()
+                .and_then(|n| links.get(n));
+            match link {
+                Some(link) => {
+                    pending_links.push(link.clone());
+                    format!("%{}-{}%", self.placeholder_prefix, pending_links.len() - 1)
+                }
+                None => {
+                    missing_link = true;
+                    String::new()
+                }
+            }
+        });
+
+        if missing_link {
+            return Err(());
+        }
+
         Ok(result.into_owned())
     }
 }
 
+/// A footnote on the current page, numbered by its position.
+struct Footnote {
+    /// The name that further markers can refer to it by, if any
+    name: Option<String>,
+    body: Option<String>,
+
+    /// The id of the footnote itself, and of each marker that refers to it
+    id: String,
+    marker_ids: Vec<String>,
+
+    /// Where this footnote was first referred to, in case it's never defined
+    first_marker: Node,
+}
+
 pub struct Worker<'a> {
     pub highlighter: SyntaxHighlighter,
 
@@ -131,6 +308,23 @@ pub struct Worker<'a> {
     evaluator: &'a Evaluator,
     pub ctx: HashMap<String, Arc<StoredValue>>,
     pub theme_config: serde_json::map::Map<String, serde_json::Value>,
+
+    /// What the current page has contributed to the evaluator, for the build cache
+    pub page_refdefs: Vec<(String, RefDef)>,
+    pub page_toctree_entries: Vec<(Slug, Option<String>, EntrySource)>,
+    pub page_assets: Vec<Asset>,
+    pub page_version_notes: Vec<VersionNote>,
+    pub page_counters: HashMap<Counter, usize>,
+    dependencies: Vec<PathBuf>,
+    footnotes: Vec<Footnote>,
+    n_tab_sets: usize,
+
+    /// The ids given to headings on the current page
+    anchors: HashSet<String>,
+    excluded: bool,
+    first_file_id: FileID,
+    n_errors: Cell<usize>,
+    n_warnings: Cell<usize>,
 }
 
 impl<'a> Worker<'a> {
@@ -148,6 +342,19 @@ impl<'a> Worker<'a> {
             evaluator: evaluator,
             ctx: HashMap::new(),
             theme_config: serde_json::map::Map::new(),
+            page_refdefs: vec![],
+            page_toctree_entries: vec![],
+            page_assets: vec![],
+            page_version_notes: vec![],
+            page_counters: HashMap::new(),
+            dependencies: vec![],
+            footnotes: vec![],
+            n_tab_sets: 0,
+            anchors: HashSet::new(),
+            excluded: false,
+            first_file_id: 0,
+            n_errors: Cell::new(0),
+            n_warnings: Cell::new(0),
         }
     }
 
@@ -162,8 +369,8 @@ impl<'a> Worker<'a> {
 
                 match self.lookup(node, directive_name.as_ref(), &children[1..]) {
                     Ok(s) => s,
-                    Err(_) => {
-                        self.error(node, "Error evaluating node");
+                    Err(diagnostic) => {
+                        self.report(&diagnostic.or_at(node));
                         String::new()
                     }
                 }
@@ -173,15 +380,29 @@ impl<'a> Worker<'a> {
         }
     }
 
-    pub fn lookup(&mut self, node: &Node, key: &str, args: &[Node]) -> Result<String, ()> {
+    pub fn lookup(&mut self, node: &Node, key: &str, args: &[Node]) -> Result<String, Diagnostic> {
         let stored = match self.ctx
             .get(key)
             .or_else(|| self.evaluator.prelude_ctx.get(key))
         {
             Some(val) => Arc::clone(val),
             None => {
-                self.error(node, &format!("Unknown name: '{}'", key));
-                return Err(());
+                // Point at the name itself rather than the whole expression
+                let name_node = match node.value {
+                    NodeValue::Children(ref children) if !children.is_empty() => &children[0],
+                    _ => node,
+                };
+                let diagnostic =
+                    Diagnostic::error(ErrorCode::UnknownName, format!("unknown name '{}'", key))
+                        .at(name_node);
+                let names = self.ctx
+                    .keys()
+                    .chain(self.evaluator.prelude_ctx.keys())
+                    .map(|name| name.as_ref());
+                return Err(match diagnostic::closest_match(key, names) {
+                    Some(name) => diagnostic.with_note(format!("did you mean '{}'?", name)),
+                    None => diagnostic,
+                });
             }
         };
 
@@ -196,6 +417,46 @@ impl<'a> Worker<'a> {
         self.current_level = 0;
         self.ctx.clear();
         self.theme_config.clear();
+        self.page_refdefs.clear();
+        self.page_toctree_entries.clear();
+        self.page_assets.clear();
+        self.page_version_notes.clear();
+        self.page_counters.clear();
+        self.dependencies.clear();
+        self.footnotes.clear();
+        self.n_tab_sets = 0;
+        self.anchors.clear();
+        self.excluded = false;
+        self.first_file_id = self.parser.next_file_id();
+        self.n_errors.set(0);
+        self.n_warnings.set(0);
+    }
+
+    /// Every file parsed, read, or referenced as an asset while evaluating the current
+    /// page.
+    pub fn get_dependencies(&self) -> Vec<PathBuf> {
+        let mut paths = self.parser.get_paths_since(self.first_file_id).to_owned();
+        paths.extend(self.page_assets.iter().map(|asset| asset.source_path.to_owned()));
+        paths.extend(self.dependencies.iter().cloned());
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Record that the current page reads a file that the parser doesn't know about,
+    /// so that the page is rebuilt when the file changes.
+    pub fn add_dependency(&mut self, path: PathBuf) {
+        self.dependencies.push(path);
+    }
+
+    /// The number of errors reported while evaluating the current page.
+    pub fn get_error_count(&self) -> usize {
+        self.n_errors.get()
+    }
+
+    /// The number of warnings reported while evaluating the current page.
+    pub fn get_warning_count(&self) -> usize {
+        self.n_warnings.get()
     }
 
     pub fn get_slug(&self) -> &Slug {
@@ -204,12 +465,100 @@ impl<'a> Worker<'a> {
             .expect("Requested slug before set")
     }
 
-    pub fn add_asset(&self, path: &str) -> Result<String, ()> {
+    /// The URL of a path in the output directory relative to the current page.
+    pub fn path_to(&self, path: &str) -> String {
+        self.get_slug().path_to(path, self.evaluator.pretty_url)
+    }
+
+    /// Number a set of tabs, counting from 1 on each page.
+    pub fn next_tab_set(&mut self) -> usize {
+        self.n_tab_sets += 1;
+        self.n_tab_sets
+    }
+
+    /// Reserve an id on the current page, appending -2, -3, and so on if it's taken.
+    pub fn unique_anchor(&mut self, id: String) -> String {
+        let mut anchor = id.to_owned();
+        let mut n = 1;
+        while self.anchors.contains(&anchor) {
+            n += 1;
+            anchor = format!("{}-{}", id, n);
+        }
+
+        self.anchors.insert(anchor.to_owned());
+        anchor
+    }
+
+    /// Reserve an id that content gave an element on the current page, reporting it
+    /// if another element already has it. Returns whether the id was free.
+    pub fn claim_anchor(&mut self, node: &Node, id: &str) -> bool {
+        if self.anchors.insert(id.to_owned()) {
+            return true;
+        }
+
+        self.report(&Diagnostic::error(
+            ErrorCode::DuplicateReference,
+            format!("id '{}' is used more than once on this page", id),
+        ).at(node));
+        false
+    }
+
+    /// Remove the placeholders that references leave in text that isn't linked yet.
+    pub fn strip_placeholders(&self, text: &str) -> String {
+        self.evaluator
+            .placeholder_pattern
+            .replace_all(text, "")
+            .into_owned()
+    }
+
+    /// Count another figure or table on this page, returning its number on the page
+    /// and a placeholder for the label that readers see, which depends on the page's
+    /// place in the toctree.
+    pub fn next_number(&mut self, counter: Counter) -> (usize, String) {
+        let n = {
+            let count = self.page_counters.entry(counter).or_insert(0);
+            *count += 1;
+            *count
+        };
+
+        let slug = self.get_slug().to_string();
+        (n, self.get_placeholder(slug, PlaceholderAction::Number(counter, n)))
+    }
+
+    /// Register a file in the content directory to be copied into the output's static
+    /// directory, and return the URL of the copy relative to the current page.
+    pub fn add_asset(&mut self, node: &Node, path: &str) -> Result<String, Diagnostic> {
+        let path = path.trim_start_matches('/');
+        let output_path = Path::new(path);
+        let escapes_root = output_path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+
+        if escapes_root {
+            return Err(Diagnostic::error(
+                ErrorCode::InvalidArgument,
+                format!("asset must be within the content directory: '{}'", path),
+            ).at(node));
+        }
+
+        let source_path = self.evaluator.root_path.join(output_path);
+        if !source_path.is_file() {
+            return Err(Diagnostic::error(
+                ErrorCode::MissingAsset,
+                format!("asset not found: '{}'", source_path.to_string_lossy()),
+            ).at(node));
+        }
+
+        self.page_assets.push(Asset {
+            source_path,
+            output_path: output_path.to_owned(),
+        });
+
         let output_slug = Slug::new(format!("_static/{}", path));
         let slug = self.current_slug
             .as_ref()
             .expect("current_slug not yet initialized");
-        Ok(slug.path_to(output_slug.as_ref(), true))
+        Ok(slug.path_to(output_slug.as_ref(), self.evaluator.pretty_url))
     }
 
     pub fn register<S: Into<String>>(
@@ -227,24 +576,100 @@ impl<'a> Worker<'a> {
         format!("%{}-{}%", self.evaluator.placeholder_prefix, txn.len() - 1)
     }
 
-    pub fn insert_refdef(&self, refid: String, refdef: RefDef) {
-        self.evaluator
+    /// Define a reference target. Warns if another page already defined it.
+    pub fn insert_refdef(&mut self, node: &Node, refid: String, refdef: RefDef) {
+        self.page_refdefs.push((refid.to_owned(), refdef.clone()));
+        let previous = self.evaluator
             .refdefs
             .write()
             .unwrap()
-            .insert(refid, refdef);
+            .insert(refid.to_owned(), refdef);
+
+        if let Some(previous) = previous {
+            if &previous.slug != self.get_slug() {
+                self.report(&Diagnostic::warning(
+                    ErrorCode::DuplicateReference,
+                    format!("reference '{}' is defined more than once", refid),
+                ).at(node)
+                    .with_note(format!("previously defined by page '{}'", previous.slug)));
+            }
+        }
+    }
+
+    /// Record content marked as changed in a version, returning an id for its callout
+    /// that is unique within the page.
+    pub fn add_version_note(&mut self, change: Change, version: &str, summary: &str) -> String {
+        let id = self.unique_anchor(format!("version-{}-{}", change.name(), version));
+
+        let note = VersionNote {
+            change,
+            version: version.to_owned(),
+            slug: self.get_slug().to_owned(),
+            id: id.to_owned(),
+            summary: summary.to_owned(),
+        };
+        self.page_version_notes.push(note.clone());
+        self.evaluator.version_notes.write().unwrap().push(note);
+        id
+    }
+
+    pub fn tags(&self) -> &HashSet<String> {
+        &self.evaluator.tags
+    }
+
+    /// Leave the current page out of the build.
+    pub fn exclude_page(&mut self) {
+        self.excluded = true;
+    }
+
+    pub fn is_excluded(&self) -> bool {
+        self.excluded
+    }
+
+    /// Withdraw the references and toctree entries that an excluded page defined, so
+    /// that it drops out of navigation.
+    pub fn retract_page(&mut self) {
+        let slug = self.current_slug.as_ref().unwrap();
+
+        {
+            let mut refdefs = self.evaluator.refdefs.write().unwrap();
+            for (refid, _) in &self.page_refdefs {
+                if refdefs.get(refid).map(|refdef| &refdef.slug) == Some(slug) {
+                    refdefs.remove(refid);
+                }
+            }
+        }
+
+        self.evaluator
+            .version_notes
+            .write()
+            .unwrap()
+            .retain(|note| &note.slug != slug);
+
+        self.evaluator.toctree.write().unwrap().exclude(slug);
     }
 
-    pub fn add_to_toctree(&self, slug: Slug, title: Option<String>) {
+    /// Add an entry to the current page's toctree, given the node that it came from.
+    pub fn add_to_toctree(&mut self, node: &Node, slug: Slug, title: Option<String>) {
+        let source = EntrySource {
+            path: self.parser
+                .get_node_source_path(node)
+                .map(|path| path.to_owned())
+                .unwrap_or_default(),
+            span: node.span,
+        };
+
+        self.page_toctree_entries
+            .push((slug.to_owned(), title.to_owned(), source.clone()));
         let current_slug = self.current_slug.as_ref().unwrap();
         self.evaluator
             .toctree
             .write()
             .unwrap()
-            .add(current_slug, slug, title);
+            .add(current_slug, slug, title, source);
     }
 
-    pub fn handle_heading(&mut self, level: i8) -> Result<String, ()> {
+    pub fn handle_heading(&mut self, level: i8) -> Result<String, Diagnostic> {
         let prefix = if level == self.current_level + 1 {
             "<section>".to_owned()
         } else if level == self.current_level {
@@ -252,7 +677,15 @@ impl<'a> Worker<'a> {
         } else if level < self.current_level {
             "</section>".repeat((self.current_level - level) as usize)
         } else {
-            return Err(());
+            let diagnostic = Diagnostic::error(
+                ErrorCode::InvalidHeadingLevel,
+                format!("heading level {} skips a level", level),
+            );
+            return Err(if self.current_level == 0 {
+                diagnostic.with_note("the first heading on a page must be an h1")
+            } else {
+                diagnostic.with_note(format!("the enclosing heading is an h{}", self.current_level))
+            });
         };
 
         self.current_level = level;
@@ -263,6 +696,106 @@ impl<'a> Worker<'a> {
         "</section>".repeat(self.current_level as usize)
     }
 
+    /// Return a marker linking to a footnote, defining the footnote's body if given.
+    /// A named footnote may have any number of markers, but only one definition.
+    pub fn add_footnote(
+        &mut self,
+        node: &Node,
+        name: Option<String>,
+        body: Option<String>,
+    ) -> Result<String, Diagnostic> {
+        let existing = name.as_ref().and_then(|name| {
+            self.footnotes
+                .iter()
+                .position(|footnote| footnote.name.as_ref() == Some(name))
+        });
+
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                let id = self.unique_anchor(format!("fn-{}", self.footnotes.len() + 1));
+                self.footnotes.push(Footnote {
+                    name: name.to_owned(),
+                    body: None,
+                    id,
+                    marker_ids: vec![],
+                    first_marker: node.clone(),
+                });
+                self.footnotes.len() - 1
+            }
+        };
+
+        if body.is_some() && self.footnotes[index].body.is_some() {
+            return Err(Diagnostic::error(
+                ErrorCode::DuplicateReference,
+                format!("footnote '{}' is defined more than once", name.unwrap_or_default()),
+            ).at(node));
+        }
+
+        // Another element on the page may have taken the usual ids
+        let number = index + 1;
+        let marker_id = footnote_marker_id(number, self.footnotes[index].marker_ids.len() + 1);
+        let marker_id = self.unique_anchor(marker_id);
+
+        let footnote = &mut self.footnotes[index];
+        if body.is_some() {
+            footnote.body = body;
+        }
+
+        footnote.marker_ids.push(marker_id.to_owned());
+        Ok(format!(
+            r##"<sup class="footnote-ref"><a href="#{}" id="{}" role="doc-noteref">{}</a></sup>"##,
+            footnote.id,
+            marker_id,
+            number
+        ))
+    }
+
+    /// Emit the current page's footnotes, with links back to each of their markers.
+    pub fn close_footnotes(&mut self) -> String {
+        if self.footnotes.is_empty() {
+            return String::new();
+        }
+
+        let footnotes: Vec<_> = self.footnotes.drain(..).collect();
+        let mut result = vec![
+            Cow::Borrowed(r#"<section class="footnotes" role="doc-endnotes"><ol>"#),
+        ];
+
+        for footnote in footnotes {
+            let body = match footnote.body {
+                Some(body) => body,
+                None => {
+                    let name = footnote.name.unwrap_or_default();
+                    self.report(&Diagnostic::error(
+                        ErrorCode::UnknownReference,
+                        format!("footnote '{}' is never defined", name),
+                    ).at(&footnote.first_marker));
+                    String::new()
+                }
+            };
+
+            result.push(Cow::Owned(format!(r#"<li id="{}">{}"#, footnote.id, body)));
+            for (i, marker_id) in footnote.marker_ids.iter().enumerate() {
+                let label = if footnote.marker_ids.len() == 1 {
+                    "↩".to_owned()
+                } else {
+                    format!("↩<sup>{}</sup>", i + 1)
+                };
+
+                result.push(Cow::Owned(format!(
+                    r##" <a href="#{}" class="footnote-backref" role="doc-backlink">{}</a>"##,
+                    marker_id,
+                    label
+                )));
+            }
+            result.push(Cow::Borrowed("</li>"));
+        }
+
+        result.push(Cow::Borrowed("</ol></section>"));
+        result.concat()
+    }
+
     pub fn get_source_path(&self, node: &Node, path: &str) -> PathBuf {
         if path.starts_with('/') {
             self.evaluator
@@ -278,27 +811,18 @@ impl<'a> Worker<'a> {
         }
     }
 
-    pub fn log(&self, node: &Node, message: &str, level: log::LogLevel) {
-        let file_path = self.parser.get_node_source_path(node);
-        log!(
-            level,
-            "{}\n  --> {}:{}:?",
-            message,
-            file_path.unwrap_or_else(|| Path::new("")).to_string_lossy(),
-            if node.lineno >= 0 {
-                node.lineno.to_string()
-            } else {
-                "?".to_owned()
-            }
-        );
-    }
-
-    #[allow(dead_code)]
-    pub fn warn(&self, node: &Node, message: &str) {
-        self.log(node, message, log::LogLevel::Warn);
-    }
+    /// Log a diagnostic along with the source line that it refers to.
+    pub fn report(&self, diagnostic: &Diagnostic) {
+        let path = diagnostic
+            .location
+            .as_ref()
+            .and_then(|location| location.file_id)
+            .and_then(|file_id| self.parser.get_path(file_id));
 
-    pub fn error(&self, node: &Node, message: &str) {
-        self.log(node, message, log::LogLevel::Error);
+        let count = match self.evaluator.reporter.report(diagnostic, path) {
+            Level::Error => &self.n_errors,
+            Level::Warning => &self.n_warnings,
+        };
+        count.set(count.get() + 1);
     }
 }
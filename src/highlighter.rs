@@ -1,10 +1,23 @@
+use std::collections::HashSet;
 use lazycell::LazyCell;
-use syntect::highlighting::ThemeSet;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, ThemeSet};
+use syntect::html::{styles_to_coloured_html, IncludeBackground};
 use syntect::parsing::SyntaxSet;
-use syntect;
+use diagnostic;
 
 pub static DEFAULT_SYNTAX_THEME: &str = "base16-ocean.light";
 
+/// How to lay out the lines of a highlighted block of code.
+#[derive(Debug, Default, PartialEq)]
+pub struct LineOptions {
+    /// The number of the first line, if lines should be numbered
+    pub first_line: Option<usize>,
+
+    /// Lines to emphasize, counting the first line of the block as 1
+    pub emphasize: HashSet<usize>,
+}
+
 pub struct SyntaxHighlighter {
     syntax_set: LazyCell<SyntaxSet>,
     theme_set: LazyCell<ThemeSet>,
@@ -20,18 +33,83 @@ impl SyntaxHighlighter {
         }
     }
 
-    pub fn highlight(&self, language: &str, code: &str) -> Result<String, ()> {
-        let syntax_set = self.syntax_set
-            .borrow_with(SyntaxSet::load_defaults_newlines);
+    fn syntax_set(&self) -> &SyntaxSet {
+        self.syntax_set
+            .borrow_with(SyntaxSet::load_defaults_newlines)
+    }
+
+    /// Highlight code written in the language named by either a file extension or
+    /// a syntax name, such as "py" or "Python". If lines are numbered or emphasized,
+    /// each line is wrapped in a span for themes to style.
+    pub fn highlight(
+        &self,
+        language: &str,
+        code: &str,
+        options: &LineOptions,
+    ) -> Result<String, ()> {
+        let syntax_set = self.syntax_set();
         let theme_set = self.theme_set.borrow_with(ThemeSet::load_defaults);
 
-        let syntax = syntax_set.find_syntax_by_extension(language).ok_or(())?;
+        let syntax = syntax_set.find_syntax_by_token(language).ok_or(())?;
         let theme = &theme_set.themes[&self.theme];
+        let background = theme.settings.background.unwrap_or(Color::WHITE);
+        let wrap_lines = options.first_line.is_some() || !options.emphasize.is_empty();
+
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        let mut output = format!(
+            "<pre style=\"background-color:#{:02x}{:02x}{:02x};\">\n",
+            background.r,
+            background.g,
+            background.b
+        );
+
+        for (i, line) in code.lines().enumerate() {
+            let regions = highlighter.highlight(line);
+            let html =
+                styles_to_coloured_html(&regions, IncludeBackground::IfDifferent(background));
+            if !wrap_lines {
+                output.push_str(&html);
+                output.push('\n');
+                continue;
+            }
+
+            if options.emphasize.contains(&(i + 1)) {
+                output.push_str(r#"<span class="code-line code-line-emphasized">"#);
+            } else {
+                output.push_str(r#"<span class="code-line">"#);
+            }
+
+            if let Some(first_line) = options.first_line {
+                output.push_str(&format!(
+                    r#"<span class="code-lineno">{}</span>"#,
+                    first_line + i
+                ));
+            }
+
+            output.push_str(&html);
+            output.push_str("\n</span>");
+        }
+
+        output.push_str("</pre>\n");
+        Ok(output)
+    }
+
+    /// Find the known language name most similar to the given unknown one.
+    pub fn suggest_language(&self, language: &str) -> Option<String> {
+        let syntax_set = self.syntax_set();
+        let names: Vec<String> = syntax_set
+            .syntaxes()
+            .iter()
+            .flat_map(|syntax| {
+                syntax
+                    .file_extensions
+                    .iter()
+                    .cloned()
+                    .chain(Some(syntax.name.to_lowercase()))
+            })
+            .collect();
 
-        Ok(syntect::html::highlighted_snippet_for_string(
-            code,
-            syntax,
-            theme,
-        ))
+        diagnostic::closest_match(&language.to_lowercase(), names.iter().map(|s| s.as_ref()))
+            .map(|s| s.to_owned())
     }
 }
@@ -33,9 +33,11 @@ default = "default.html"
 <head>
 <title>{{project.title}}{{#if page.title}} - {{striptags page.title}}{{/if}}</title>
 <meta charset="utf-8">
+{{#if canonical_url}}<link rel="canonical" href="{{canonical_url}}">{{/if}}
 </head>
 <body>
 <nav class="root-toc">
+{{search}}
 {{toctree "index"}}
 </nav>
 <div class="body">
@@ -35,6 +35,7 @@ lazy_static! {
          b"p".as_ref(),
          b"pre".as_ref(),
          b"section".as_ref(),
+         b"summary".as_ref(),
          b"table".as_ref(),
          b"ul".as_ref(),
     ].into_iter().collect();
@@ -156,4 +157,29 @@ Sed facilisis
 
         assert_eq!(inject_paragraphs(src), expected.to_owned());
     }
+
+    #[test]
+    fn test_tabs() {
+        let src = concat!(
+            "Before\n\n",
+            r#"<div class="tabs"><input type="radio" class="tabs__input" name="tabs-1" id="tabs-1-1" checked>"#,
+            r#"<label class="tabs__label" id="tabs-1-1-label" for="tabs-1-1">Linux</label>"#,
+            r#"<div class="tabs__panel" role="region" aria-labelledby="tabs-1-1-label">One"#,
+            "\n\nTwo</div></div>",
+            r#"<script src="_static/tabs.js" defer></script>"#,
+            "\n\nAfter"
+        );
+
+        let expected = concat!(
+            "Before\n\n",
+            r#"<div class="tabs"><input type="radio" class="tabs__input" name="tabs-1" id="tabs-1-1" checked>"#,
+            r#"<label class="tabs__label" id="tabs-1-1-label" for="tabs-1-1">Linux</label>"#,
+            r#"<div class="tabs__panel" role="region" aria-labelledby="tabs-1-1-label">One"#,
+            "\n\n<p>Two</div></div>",
+            r#"<script src="_static/tabs.js" defer></script>"#,
+            "\n\n<p>After"
+        );
+
+        assert_eq!(inject_paragraphs(src), expected.to_owned());
+    }
 }
@@ -14,36 +14,115 @@ lazy_static! {
         | [^\(\)=\s"]+"#).expect("Failed to compile lexer regex");
 }
 
+/// A point in a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Position {
+    /// Byte offset from the start of the file
+    pub offset: usize,
+
+    /// Zero-indexed line number, or -1 if unknown
+    pub lineno: i32,
+
+    /// Zero-indexed column, in characters
+    pub column: u32,
+}
+
+impl Position {
+    pub fn new(offset: usize, lineno: i32, column: u32) -> Self {
+        Position {
+            offset,
+            lineno,
+            column,
+        }
+    }
+}
+
+/// The region of a source file from start up to, but not including, end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+
+    /// The span of something that didn't come from a source file.
+    pub fn unknown() -> Self {
+        let position = Position::new(0, -1, 0);
+        Span::new(position, position)
+    }
+
+    pub fn lineno(&self) -> i32 {
+        self.start.lineno
+    }
+
+    /// The smallest span covering both self and other.
+    pub fn to(&self, other: &Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
 #[derive(Debug, PartialEq)]
-pub enum Token<'a> {
-    StartBlock(i32),
+pub enum TokenKind<'a> {
+    StartBlock,
     RightParen,
-    Rocket(i32),
+    Rocket,
     Dedent,
-    Text(i32, &'a str),
-    Quote(i32),
+    Text(&'a str),
+    Quote,
 }
 
-pub fn lex(data: &str) -> Vec<Token> {
-    let data_bytes = data.as_bytes();
-    let mut lineno: i32 = 0;
-    let mut last_match_start: usize = 0;
+#[derive(Debug, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub span: Span,
+}
+
+/// Tracks the line and column of successive offsets into a source file.
+struct Cursor<'a> {
+    data: &'a str,
+    position: Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn advance(&mut self, offset: usize) -> Position {
+        let skipped = &self.data[self.position.offset..offset];
+        match skipped.rfind('\n') {
+            Some(i) => {
+                self.position.lineno += naive_count_32(skipped.as_bytes(), b'\n') as i32;
+                self.position.column = skipped[i + 1..].chars().count() as u32;
+            }
+            None => self.position.column += skipped.chars().count() as u32,
+        }
+
+        self.position.offset = offset;
+        self.position
+    }
+}
+
+pub fn lex<'a>(data: &'a str) -> Vec<Token<'a>> {
+    let mut cursor = Cursor {
+        data,
+        position: Position::new(0, 0, 0),
+    };
     let mut tokens: Vec<Token> = vec![];
     let mut indent: Vec<usize> = vec![0];
 
     for pat_match in PAT_TOKENS.find_iter(data) {
-        lineno += naive_count_32(&data_bytes[last_match_start..pat_match.start()], b'\n') as i32;
-        last_match_start = pat_match.start();
-        let token_text = pat_match.as_str();
+        let start = cursor.advance(pat_match.start());
+        let mut token_text = pat_match.as_str();
         let bytes = token_text.as_bytes();
 
-        let token = match bytes[0] {
-            b')' => Token::RightParen,
-            b'"' => Token::Quote(lineno),
-            _ if bytes == b"(:" => Token::StartBlock(lineno),
+        let kind = match bytes[0] {
+            b')' => TokenKind::RightParen,
+            b'"' => TokenKind::Quote,
+            _ if bytes == b"(:" => TokenKind::StartBlock,
             _ if bytes.starts_with(b"=>\n") => {
                 indent.push(bytes.len() - 3);
-                Token::Rocket(lineno)
+                TokenKind::Rocket
             }
             _ if bytes.starts_with(b"\n") => {
                 let mut current_indentation_level =
@@ -54,20 +133,32 @@ pub fn lex(data: &str) -> Vec<Token> {
                     indent.pop();
                     current_indentation_level =
                         *(indent.last().expect("Indentation stack is empty"));
-                    tokens.push(Token::Dedent);
+                    tokens.push(Token {
+                        kind: TokenKind::Dedent,
+                        span: Span::new(start, start),
+                    });
                 }
 
                 let new_end = token_text.len() - current_indentation_level;
-                Token::Text(lineno, &token_text[..new_end])
+                token_text = &token_text[..new_end];
+                TokenKind::Text(token_text)
             }
-            _ => Token::Text(lineno, token_text),
+            _ => TokenKind::Text(token_text),
         };
 
-        tokens.push(token)
+        let end = cursor.advance(pat_match.start() + token_text.len());
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, end),
+        });
     }
 
+    let end = cursor.advance(data.len());
     while indent.len() > 1 {
-        tokens.push(Token::Dedent);
+        tokens.push(Token {
+            kind: TokenKind::Dedent,
+            span: Span::new(end, end),
+        });
         indent.pop();
     }
 
@@ -78,36 +169,44 @@ pub fn lex(data: &str) -> Vec<Token> {
 mod tests {
     use super::*;
 
+    /// Reduce each token to its kind and starting line, for brevity.
+    fn lex_lines<'a>(data: &'a str) -> Vec<(i32, TokenKind<'a>)> {
+        lex(data)
+            .into_iter()
+            .map(|token| (token.span.lineno(), token.kind))
+            .collect()
+    }
+
     #[test]
     fn test_empty() {
-        assert_eq!(lex(""), vec![]);
+        assert_eq!(lex_lines(""), vec![]);
     }
 
     #[test]
     fn test_expression() {
         assert_eq!(
-            lex(r#"(:foo bar (:a "b c") "baz" )"#),
+            lex_lines(r#"(:foo bar (:a "b c") "baz" )"#),
             vec![
-                Token::StartBlock(0),
-                Token::Text(0, "foo"),
-                Token::Text(0, " "),
-                Token::Text(0, "bar"),
-                Token::Text(0, " "),
-                Token::StartBlock(0),
-                Token::Text(0, "a"),
-                Token::Text(0, " "),
-                Token::Quote(0),
-                Token::Text(0, "b"),
-                Token::Text(0, " "),
-                Token::Text(0, "c"),
-                Token::Quote(0),
-                Token::RightParen,
-                Token::Text(0, " "),
-                Token::Quote(0),
-                Token::Text(0, "baz"),
-                Token::Quote(0),
-                Token::Text(0, " "),
-                Token::RightParen,
+                (0, TokenKind::StartBlock),
+                (0, TokenKind::Text("foo")),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::Text("bar")),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::StartBlock),
+                (0, TokenKind::Text("a")),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::Quote),
+                (0, TokenKind::Text("b")),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::Text("c")),
+                (0, TokenKind::Quote),
+                (0, TokenKind::RightParen),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::Quote),
+                (0, TokenKind::Text("baz")),
+                (0, TokenKind::Quote),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::RightParen),
             ]
         );
     }
@@ -115,7 +214,7 @@ mod tests {
     #[test]
     fn test_rocket() {
         assert_eq!(
-            lex(
+            lex_lines(
                 "
 (:note \"a title\" =>
   stuff  1
@@ -134,50 +233,50 @@ mod tests {
 (:h2 foo)"
             ),
             vec![
-                Token::Text(0, "\n"),
-                Token::StartBlock(1),
-                Token::Text(1, "note"),
-                Token::Text(1, " "),
-                Token::Quote(1),
-                Token::Text(1, "a"),
-                Token::Text(1, " "),
-                Token::Text(1, "title"),
-                Token::Quote(1),
-                Token::Text(1, " "),
-                Token::Rocket(1),
-                Token::Text(2, "stuff"),
-                Token::Text(2, "  "),
-                Token::Text(2, "1"),
-                Token::Text(2, "\n\n"),
-                Token::Text(4, "stuff"),
-                Token::Text(4, " "),
-                Token::Text(4, "2"),
-                Token::Text(4, "\n\n"),
-                Token::StartBlock(6),
-                Token::Text(6, "note"),
-                Token::Text(6, " "),
-                Token::Rocket(6),
-                Token::Text(7, "more"),
-                Token::Text(7, " "),
-                Token::Text(7, "stuff"),
-                Token::Text(7, "\n\n"),
-                Token::Text(9, "second"),
-                Token::Text(9, " "),
-                Token::Text(9, "="),
-                Token::Text(9, ">paragraph"),
-                Token::Dedent,
-                Token::Text(9, "\n\n"),
-                Token::Text(11, "closing"),
-                Token::Text(11, " "),
-                Token::Text(11, "nested"),
-                Token::Text(11, "\n\n"),
-                Token::Dedent,
-                Token::Text(13, "\n\n"),
-                Token::StartBlock(15),
-                Token::Text(15, "h2"),
-                Token::Text(15, " "),
-                Token::Text(15, "foo"),
-                Token::RightParen,
+                (0, TokenKind::Text("\n")),
+                (1, TokenKind::StartBlock),
+                (1, TokenKind::Text("note")),
+                (1, TokenKind::Text(" ")),
+                (1, TokenKind::Quote),
+                (1, TokenKind::Text("a")),
+                (1, TokenKind::Text(" ")),
+                (1, TokenKind::Text("title")),
+                (1, TokenKind::Quote),
+                (1, TokenKind::Text(" ")),
+                (1, TokenKind::Rocket),
+                (2, TokenKind::Text("stuff")),
+                (2, TokenKind::Text("  ")),
+                (2, TokenKind::Text("1")),
+                (2, TokenKind::Text("\n\n")),
+                (4, TokenKind::Text("stuff")),
+                (4, TokenKind::Text(" ")),
+                (4, TokenKind::Text("2")),
+                (4, TokenKind::Text("\n\n")),
+                (6, TokenKind::StartBlock),
+                (6, TokenKind::Text("note")),
+                (6, TokenKind::Text(" ")),
+                (6, TokenKind::Rocket),
+                (7, TokenKind::Text("more")),
+                (7, TokenKind::Text(" ")),
+                (7, TokenKind::Text("stuff")),
+                (7, TokenKind::Text("\n\n")),
+                (9, TokenKind::Text("second")),
+                (9, TokenKind::Text(" ")),
+                (9, TokenKind::Text("=")),
+                (9, TokenKind::Text(">paragraph")),
+                (9, TokenKind::Dedent),
+                (9, TokenKind::Text("\n\n")),
+                (11, TokenKind::Text("closing")),
+                (11, TokenKind::Text(" ")),
+                (11, TokenKind::Text("nested")),
+                (11, TokenKind::Text("\n\n")),
+                (13, TokenKind::Dedent),
+                (13, TokenKind::Text("\n\n")),
+                (15, TokenKind::StartBlock),
+                (15, TokenKind::Text("h2")),
+                (15, TokenKind::Text(" ")),
+                (15, TokenKind::Text("foo")),
+                (15, TokenKind::RightParen),
             ]
         );
     }
@@ -185,21 +284,21 @@ mod tests {
     #[test]
     fn test_rocket_indentation() {
         assert_eq!(
-            lex(
+            lex_lines(
                 r#"
 (:note =>
   stuff
     stuff"#.trim()
             ),
             vec![
-                Token::StartBlock(0),
-                Token::Text(0, "note"),
-                Token::Text(0, " "),
-                Token::Rocket(0),
-                Token::Text(1, "stuff"),
-                Token::Text(1, "\n  "),
-                Token::Text(2, "stuff"),
-                Token::Dedent,
+                (0, TokenKind::StartBlock),
+                (0, TokenKind::Text("note")),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::Rocket),
+                (1, TokenKind::Text("stuff")),
+                (1, TokenKind::Text("\n  ")),
+                (2, TokenKind::Text("stuff")),
+                (2, TokenKind::Dedent),
             ]
         );
     }
@@ -207,7 +306,7 @@ mod tests {
     #[test]
     fn test_multiline() {
         assert_eq!(
-            lex(
+            lex_lines(
                 "
 (:h2 =>
   Example
@@ -218,20 +317,84 @@ mod tests {
                     .trim()
             ),
             vec![
-                Token::StartBlock(0),
-                Token::Text(0, "h2"),
-                Token::Text(0, " "),
-                Token::Rocket(0),
-                Token::Text(1, "Example"),
-                Token::Dedent,
-                Token::Text(1, "\n\n\n"),
-                Token::StartBlock(4),
-                Token::Text(4, "``"),
-                Token::Text(4, " "),
-                Token::Text(4, "use"),
-                Token::Text(4, "\n"),
-                Token::Text(5, "<database>"),
-                Token::RightParen,
+                (0, TokenKind::StartBlock),
+                (0, TokenKind::Text("h2")),
+                (0, TokenKind::Text(" ")),
+                (0, TokenKind::Rocket),
+                (1, TokenKind::Text("Example")),
+                (1, TokenKind::Dedent),
+                (1, TokenKind::Text("\n\n\n")),
+                (4, TokenKind::StartBlock),
+                (4, TokenKind::Text("``")),
+                (4, TokenKind::Text(" ")),
+                (4, TokenKind::Text("use")),
+                (4, TokenKind::Text("\n")),
+                (5, TokenKind::Text("<database>")),
+                (5, TokenKind::RightParen),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let span = |start: (usize, i32, u32), end: (usize, i32, u32)| {
+            Span::new(
+                Position::new(start.0, start.1, start.2),
+                Position::new(end.0, end.1, end.2),
+            )
+        };
+
+        assert_eq!(
+            lex("(:é \"b\" =>\n  ok\n)"),
+            vec![
+                Token {
+                    kind: TokenKind::StartBlock,
+                    span: span((0, 0, 0), (2, 0, 2)),
+                },
+                Token {
+                    kind: TokenKind::Text("é"),
+                    span: span((2, 0, 2), (4, 0, 3)),
+                },
+                Token {
+                    kind: TokenKind::Text(" "),
+                    span: span((4, 0, 3), (5, 0, 4)),
+                },
+                Token {
+                    kind: TokenKind::Quote,
+                    span: span((5, 0, 4), (6, 0, 5)),
+                },
+                Token {
+                    kind: TokenKind::Text("b"),
+                    span: span((6, 0, 5), (7, 0, 6)),
+                },
+                Token {
+                    kind: TokenKind::Quote,
+                    span: span((7, 0, 6), (8, 0, 7)),
+                },
+                Token {
+                    kind: TokenKind::Text(" "),
+                    span: span((8, 0, 7), (9, 0, 8)),
+                },
+                Token {
+                    kind: TokenKind::Rocket,
+                    span: span((9, 0, 8), (14, 1, 2)),
+                },
+                Token {
+                    kind: TokenKind::Text("ok"),
+                    span: span((14, 1, 2), (16, 1, 4)),
+                },
+                Token {
+                    kind: TokenKind::Dedent,
+                    span: span((16, 1, 4), (16, 1, 4)),
+                },
+                Token {
+                    kind: TokenKind::Text("\n"),
+                    span: span((16, 1, 4), (17, 2, 0)),
+                },
+                Token {
+                    kind: TokenKind::RightParen,
+                    span: span((17, 2, 0), (18, 2, 1)),
+                },
             ]
         );
     }
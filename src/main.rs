@@ -21,40 +21,63 @@ extern crate toml;
 extern crate typed_arena;
 extern crate walkdir;
 
+mod cache;
+mod diagnostic;
 mod directives;
 mod evaluator;
 mod highlighter;
 mod init;
 mod inject_paragraphs;
 mod lex;
+mod numbering;
 mod page;
 mod parse;
+mod search;
+mod serve;
+mod sitemap;
 mod theme;
 mod toctree;
 
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
 use std::convert::From;
 use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
 use std::io::{self, Read, Write};
 use std::ops::DerefMut;
-use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::{env, mem, process};
-use evaluator::{Evaluator, Worker};
+use std::{env, fmt, mem, process};
+use cache::{Cache, CacheEntry};
+use diagnostic::{Counts, Diagnostic, ErrorCode, Level, Reporter};
+use evaluator::{Evaluator, RefDef, Worker};
 use inject_paragraphs::inject_paragraphs;
-use page::{Page, Slug};
+use numbering::Numbering;
+use page::{Asset, Page, Slug};
 use toctree::TocTree;
-use directives::{glossary, logic};
+use directives::{glossary, logic, tabs};
+use directives::versions::{self, Change, VersionChange};
 use scoped_threadpool::Pool;
 
+const CACHE_DIR: &str = ".rocket-cache";
+const STATIC_DIR: &str = "_static";
+
 #[derive(Debug)]
 enum LinkError {
-    UndefinedReference,
     TemplateError(handlebars::RenderError),
     IOError(io::Error),
 }
 
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkError::TemplateError(ref err) => write!(f, "{}", err),
+            LinkError::IOError(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
 impl From<handlebars::RenderError> for LinkError {
     fn from(orig: handlebars::RenderError) -> Self {
         LinkError::TemplateError(orig)
@@ -75,6 +98,16 @@ struct RawConfig {
     output: Option<PathBuf>,
     templates: HashMap<String, String>,
     theme_constants: Option<serde_json::map::Map<String, serde_json::Value>>,
+    base_url: Option<String>,
+    pretty_url: Option<bool>,
+    version: Option<String>,
+    release: Option<String>,
+    version_from_git: Option<bool>,
+    variables: Option<serde_json::map::Map<String, serde_json::Value>>,
+    admonitions: Option<BTreeMap<String, directives::AdmonitionConfig>>,
+    deprecation_window: Option<u64>,
+    whats_new: Option<String>,
+    numbering: Option<numbering::Scope>,
 }
 
 struct Project {
@@ -85,7 +118,32 @@ struct Project {
     templates: Vec<(glob::Pattern, String)>,
     theme_constants: serde_json::map::Map<String, serde_json::Value>,
     syntax_theme: String,
+    cache_dir: PathBuf,
+    cache_key: u64,
+
+    /// The short version, such as "3.4", and the full release, such as "3.4.0-rc1"
+    version: String,
+    release: String,
+
+    /// Values that content can refer to by name, such as (:edition)
+    variables: HashMap<String, String>,
 
+    /// Every kind of admonition, after applying the theme's and then the project's
+    /// configuration to the defaults
+    admonitions: BTreeMap<String, directives::AdmonitionConfig>,
+
+    /// How many breaking releases deprecated content may outlive before it is
+    /// reported, or 0 to never report it
+    deprecation_window: u64,
+
+    /// Where to write a page for each version listing what changed in it, if anywhere
+    whats_new: Option<String>,
+
+    /// Whether figures and tables are numbered on each page or each chapter
+    numbering: numbering::Scope,
+
+    /// The absolute URL at which the output is published, if known
+    base_url: Option<String>,
     pretty_url: bool,
 }
 
@@ -98,6 +156,54 @@ impl Project {
 
         let theme = config.theme.ok_or(())?;
 
+        let git_release = if config.version_from_git.unwrap_or(false) {
+            describe_git_version()
+        } else {
+            None
+        };
+
+        let version = config.version;
+        let release = git_release
+            .or(config.release)
+            .or_else(|| version.to_owned())
+            .unwrap_or_default();
+        // By default, the version is the release's major and minor components
+        let version = version.unwrap_or_else(|| {
+            let components = directives::release_components(&release);
+            components[..cmp::min(2, components.len())].join(".")
+        });
+
+        let mut admonitions: BTreeMap<_, _> = directives::DEFAULT_ADMONITIONS
+            .iter()
+            .map(|&(name, title)| {
+                let admonition = directives::AdmonitionConfig {
+                    title: Some(title.to_owned()),
+                    ..Default::default()
+                };
+                (name.to_owned(), admonition)
+            })
+            .collect();
+        let theme_admonitions = theme::Theme::load(&theme)
+            .map(|theme| theme.admonitions)
+            .unwrap_or_default();
+        for (name, admonition) in theme_admonitions
+            .into_iter()
+            .chain(config.admonitions.unwrap_or_default())
+        {
+            admonitions.entry(name).or_default().merge(admonition);
+        }
+
+        // Any change to the configuration or to Rocket itself invalidates the build cache
+        let cache_key = {
+            let mut hasher = DefaultHasher::new();
+            data.hash(&mut hasher);
+            admonitions.hash(&mut hasher);
+            release.hash(&mut hasher);
+            option_env!("CARGO_PKG_VERSION").hash(&mut hasher);
+            env!("GIT_HASH").hash(&mut hasher);
+            hasher.finish()
+        };
+
         let path_patterns: Result<Vec<_>, ()> = config
             .templates
             .iter()
@@ -116,6 +222,30 @@ impl Project {
             .syntax_theme
             .unwrap_or_else(|| highlighter::DEFAULT_SYNTAX_THEME.to_owned());
 
+        let variables = config
+            .variables
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                (name, value)
+            })
+            .collect();
+
+        // Themes see the version as project.version, unless overridden
+        let mut theme_constants = config
+            .theme_constants
+            .unwrap_or_else(serde_json::map::Map::new);
+        theme_constants
+            .entry("version")
+            .or_insert_with(|| serde_json::Value::String(version.to_owned()));
+        theme_constants
+            .entry("release")
+            .or_insert_with(|| serde_json::Value::String(release.to_owned()));
+
         Ok(Project {
             verbose: false,
             theme,
@@ -124,27 +254,48 @@ impl Project {
                 .unwrap_or_else(|| PathBuf::from("content")),
             output: config.output.unwrap_or_else(|| PathBuf::from("build")),
             templates: path_patterns,
-            theme_constants: config
-                .theme_constants
-                .unwrap_or_else(serde_json::map::Map::new),
+            theme_constants,
             syntax_theme,
-            pretty_url: true,
+            cache_dir: PathBuf::from(CACHE_DIR),
+            cache_key,
+            version,
+            release,
+            variables,
+            admonitions,
+            deprecation_window: config.deprecation_window.unwrap_or(1),
+            whats_new: config
+                .whats_new
+                .map(|prefix| prefix.trim_matches('/').to_owned()),
+            numbering: config.numbering.unwrap_or_default(),
+            base_url: config.base_url,
+            pretty_url: config.pretty_url.unwrap_or(true),
         })
     }
 
-    fn build_file(&self, worker: &mut Worker, path: &Path) -> Result<Page, ()> {
+    /// Compile a page, reporting any problems along the way. Returns None if the page
+    /// could not be compiled.
+    fn build_file(&self, worker: &mut Worker, path: &Path) -> Option<Page> {
         debug!("Compiling {}", worker.get_slug());
 
-        let node = match worker.parser.parse(path) {
-            Ok(n) => n,
+        let (node, diagnostics) = match worker.parser.parse(path) {
+            Ok(result) => result,
             Err(msg) => {
-                error!("Failed to parse '{}': {}", path.to_string_lossy(), msg);
-                return Err(());
+                worker.report(&Diagnostic::error(ErrorCode::IncludeFailed, msg));
+                return None;
             }
         };
 
+        for diagnostic in &diagnostics {
+            worker.report(diagnostic);
+        }
+
+        if diagnostics.iter().any(|d| d.level == Level::Error) {
+            return None;
+        }
+
         let mut output = worker.evaluate(&node);
         output.push_str(&worker.close_sections());
+        output.push_str(&worker.close_footnotes());
         let output = inject_paragraphs(&output);
 
         let page = Page {
@@ -152,17 +303,23 @@ impl Project {
             slug: worker.get_slug().clone(),
             body: output,
             theme_config: worker.theme_config.clone(),
+            assets: worker.page_assets.clone(),
         };
 
-        Ok(page)
+        Some(page)
     }
 
+    /// Render a page and write it to the output directory, unless the output would be
+    /// identical to what was written for previous_hash. Returns a hash identifying
+    /// the output, along with the page's entry in the search index.
     fn link_file(
         &self,
         evaluator: &Evaluator,
         page: &Page,
         renderer: &theme::Renderer,
-    ) -> Result<(), LinkError> {
+        site_hash: u64,
+        previous_hash: Option<u64>,
+    ) -> Result<(u64, search::Document), LinkError> {
         debug!("Linking {}", &page.slug);
 
         // Find the template that matches this path
@@ -172,31 +329,119 @@ impl Project {
             .map(|&(_, ref name)| name.as_ref())
             .unwrap_or("default");
 
-        let new_body = match evaluator.substitute(page) {
-            Ok(s) => s,
-            Err(_) => {
-                return Err(LinkError::UndefinedReference);
-            }
+        let new_body = evaluator.substitute(page);
+
+        let output_path = page.slug.create_output_path(&self.output, self.pretty_url);
+
+        let output_hash = {
+            let mut hasher = DefaultHasher::new();
+            site_hash.hash(&mut hasher);
+            template_name.hash(&mut hasher);
+            new_body.hash(&mut hasher);
+            serde_json::to_string(&page.theme_config)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+            hasher.finish()
         };
 
+        let document = search::Document::new(page, &new_body, self.pretty_url);
+
+        if previous_hash == Some(output_hash) && output_path.exists() {
+            debug!("Skipping unchanged {}", &page.slug);
+            return Ok((output_hash, document));
+        }
+
         let rendered = renderer.render(template_name, &self.theme_constants, page, &new_body)?;
-        let output_path = page.slug.create_output_path(&self.output, self.pretty_url);
         let output_dir = output_path.parent().expect("Couldn't get output directory");
 
         fs::create_dir_all(output_dir)?;
         let mut file = File::create(&output_path)?;
         file.write_all(rendered.as_bytes())?;
 
-        Ok(())
+        Ok((output_hash, document))
+    }
+}
+
+/// Ask git for the latest tag reachable from HEAD, such as "v3.4.0-12-gd0c3a5e", and
+/// return it without any leading "v".
+fn describe_git_version() -> Option<String> {
+    let output = match process::Command::new("git")
+        .arg("describe")
+        .arg("--tags")
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) => {
+            warn!("Failed to run git describe: {}", err);
+            return None;
+        }
+    };
+
+    if !output.status.success() {
+        warn!(
+            "Failed to describe version with git: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
     }
+
+    let described = String::from_utf8_lossy(&output.stdout);
+    let described = described.trim();
+    Some(described.trim_start_matches('v').to_owned())
 }
 
-fn build_project(project: Project, evaluator: Evaluator) {
+fn is_up_to_date(source: &Path, dest: &Path) -> bool {
+    let (source, dest) = match (fs::metadata(source), fs::metadata(dest)) {
+        (Ok(s), Ok(d)) => (s, d),
+        _ => return false,
+    };
+
+    match (source.modified(), dest.modified()) {
+        (Ok(s), Ok(d)) => source.len() == dest.len() && d >= s,
+        _ => false,
+    }
+}
+
+/// Copy assets into the output's static directory. If more than one asset has the same
+/// output path, the first one wins.
+fn copy_assets(output: &Path, assets: &[Asset], reporter: &Reporter) {
+    let static_dir = output.join(STATIC_DIR);
+    let mut seen = HashSet::new();
+
+    for asset in assets {
+        if !seen.insert(&asset.output_path) {
+            continue;
+        }
+
+        let dest = static_dir.join(&asset.output_path);
+        if is_up_to_date(&asset.source_path, &dest) {
+            continue;
+        }
+
+        debug!("Copying {}", asset.source_path.to_string_lossy());
+        let result = match dest.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }.and_then(|_| fs::copy(&asset.source_path, &dest));
+
+        if let Err(err) = result {
+            reporter.error(format!(
+                "Failed to copy asset '{}': {}",
+                asset.source_path.to_string_lossy(),
+                err
+            ));
+        }
+    }
+}
+
+/// Compile every page in the project, reusing cached pages where possible, and
+/// finish the project's toctree. Returns None if the project has no pages.
+fn compile_project(
+    project: &Arc<Project>,
+    evaluator: &Arc<Evaluator>,
+) -> Option<(Vec<CacheEntry>, TocTree)> {
     let num_cpus = num_cpus::get();
-    let project = Arc::new(project);
-    let evaluator = Arc::new(evaluator);
-    let titles: Arc<Mutex<HashMap<Slug, String>>> = Arc::new(Mutex::new(HashMap::new()));
-    let pending_pages: Arc<Mutex<Vec<Page>>> = Arc::new(Mutex::new(vec![]));
+    let pending_pages: Arc<Mutex<Vec<CacheEntry>>> = Arc::new(Mutex::new(vec![]));
 
     debug!("Crawling source directory");
 
@@ -214,19 +459,31 @@ fn build_project(project: Project, evaluator: Evaluator) {
         paths.push(entry.path().to_owned());
     }
 
-    debug!("Compiling with {} workers", num_cpus);
-    let paths = Arc::new(paths);
-    let chunk_size = (paths.len() as f32 / num_cpus as f32).ceil() as usize;
-    if chunk_size == 0 {
-        return;
+    if paths.is_empty() {
+        return None;
     }
 
+    let mut cache = Cache::load(&project.cache_dir, project.cache_key);
+    let cached_pages = cache.take_fresh(&paths, evaluator);
+
+    let paths: Vec<_> = {
+        let cached_paths: HashSet<_> = cached_pages
+            .iter()
+            .map(|entry| entry.page.source_path.to_owned())
+            .collect();
+        paths.into_iter().filter(|path| !cached_paths.contains(path)).collect()
+    };
+
+    info!("Compiling {} pages ({} unchanged)", paths.len(), cached_pages.len());
+    pending_pages.lock().unwrap().extend(cached_pages);
+
+    debug!("Compiling with {} workers", num_cpus);
+    let chunk_size = cmp::max(1, (paths.len() as f32 / num_cpus as f32).ceil() as usize);
     let chunks: Vec<_> = paths.chunks(chunk_size).map(|x| x.to_owned()).collect();
     let mut threads = Vec::with_capacity(chunks.len());
     for chunk in chunks {
-        let project = Arc::clone(&project);
-        let evaluator = Arc::clone(&evaluator);
-        let titles = Arc::clone(&titles);
+        let project = Arc::clone(project);
+        let evaluator = Arc::clone(evaluator);
         let pending_pages = Arc::clone(&pending_pages);
 
         let thread = std::thread::spawn(move || {
@@ -240,17 +497,17 @@ fn build_project(project: Project, evaluator: Evaluator) {
                 let slug = Slug::new(dir.join(stem).to_string_lossy().as_ref().to_owned());
                 worker.set_slug(slug);
 
-                match project.build_file(&mut worker, &path) {
-                    Ok(page) => {
-                        titles
-                            .lock()
-                            .unwrap()
-                            .insert(page.slug.to_owned(), page.title());
-                        pending_pages.lock().unwrap().push(page);
-                    }
-                    Err(_) => {
-                        error!("Failed to build {}", path.to_string_lossy());
+                if let Some(page) = project.build_file(&mut worker, &path) {
+                    if worker.is_excluded() {
+                        debug!("Excluding {}", page.slug);
+                        worker.retract_page();
+                        continue;
                     }
+
+                    pending_pages
+                        .lock()
+                        .unwrap()
+                        .push(CacheEntry::new(&worker, page));
                 }
             }
         });
@@ -264,56 +521,273 @@ fn build_project(project: Project, evaluator: Evaluator) {
             .expect("At least one compilation worker panicked");
     }
 
+    let pages: Vec<_> = pending_pages.lock().unwrap().drain(..).collect();
+
     let mut toctree = {
         let mut txn = evaluator.toctree.write().unwrap();
         mem::replace(txn.deref_mut(), TocTree::new_empty())
     };
 
-    toctree.finish(titles.lock().unwrap().deref());
+    toctree.finish(pages.iter().map(|entry| &entry.page), &evaluator.reporter);
+
+    Some((pages, toctree))
+}
+
+/// Generate a page for each version that content marks changes in, and define a
+/// reference to each, such as whats-new-3.2 for the prefix "whats-new".
+fn generate_whats_new(
+    project: &Project,
+    evaluator: &Evaluator,
+    entries: &[CacheEntry],
+    prefix: &str,
+) -> Vec<Page> {
+    let titles: HashMap<_, _> = entries
+        .iter()
+        .map(|entry| (entry.page.slug.to_owned(), entry.page.title()))
+        .collect();
+    let notes = evaluator.version_notes.read().unwrap();
+
+    versions::whats_new_pages(&notes, &titles, prefix, project.pretty_url)
+        .into_iter()
+        .map(|generated| {
+            let refid = format!("ref-{}", generated.slug.as_ref().replace('/', "-"));
+            let refdef = RefDef::new(&generated.title, &generated.slug);
+            evaluator.refdefs.write().unwrap().insert(refid, refdef);
+
+            let mut theme_config = serde_json::map::Map::new();
+            theme_config.insert("title".to_owned(), serde_json::Value::String(generated.title));
+            Page {
+                source_path: project
+                    .content_dir
+                    .join(format!("{}.rocket", generated.slug)),
+                slug: generated.slug,
+                body: generated.body,
+                theme_config,
+                assets: vec![],
+            }
+        })
+        .collect()
+}
+
+/// Build the project, returning the number of errors and warnings reported.
+fn build_project(project: Project, evaluator: Evaluator) -> Counts {
+    let num_cpus = num_cpus::get();
+    let project = Arc::new(project);
+    let evaluator = Arc::new(evaluator);
+
+    let (pending_pages, toctree) = match compile_project(&project, &evaluator) {
+        Some(result) => result,
+        None => return evaluator.reporter.counts(),
+    };
+
+    let whats_new_pages = match project.whats_new {
+        Some(ref prefix) => generate_whats_new(&project, &evaluator, &pending_pages, prefix),
+        None => vec![],
+    };
+
+    {
+        let counts: HashMap<_, _> = pending_pages
+            .iter()
+            .map(|entry| (&entry.page.slug, entry.counters.as_slice()))
+            .collect();
+        *evaluator.numbering.write().unwrap() =
+            Numbering::new(project.numbering, &toctree.chapters(), &counts);
+    }
 
     let theme = theme::Theme::load(&project.theme).expect("Failed to load theme");
+    let theme_assets = theme.get_assets();
+
+    // Anything that affects the rendering of every page
+    let site_hash = {
+        let mut hasher = DefaultHasher::new();
+        theme.fingerprint().hash(&mut hasher);
+        toctree.hash(&mut hasher);
+        hasher.finish()
+    };
 
     let renderer = Arc::new(
-        theme::Renderer::new(theme, Arc::new(toctree)).expect("Failed to construct renderer"),
+        theme::Renderer::new(
+            theme,
+            Arc::new(toctree),
+            project.pretty_url,
+            project.base_url.to_owned(),
+        )
+            .expect("Failed to construct renderer"),
     );
 
     debug!("Linking with {} workers", num_cpus);
 
-    let mut pool = Pool::new(num_cpus as u32);
-    pool.scoped(move |scoped| {
-        let mut pending_pages = pending_pages.lock().unwrap();
-        for page in pending_pages.drain(0..) {
-            let project = Arc::clone(&project);
-            let evaluator = Arc::clone(&evaluator);
-            let renderer = Arc::clone(&renderer);
-
-            scoped.execute(move || {
-                project
-                    .link_file(&evaluator, &page, &renderer)
-                    .expect("Failed to link page");
-            });
+    let linked_pages: Arc<Mutex<Vec<CacheEntry>>> = Arc::new(Mutex::new(vec![]));
+    let search_documents: Arc<Mutex<Vec<search::Document>>> = Arc::new(Mutex::new(vec![]));
+    for page in &whats_new_pages {
+        match project.link_file(&evaluator, page, &renderer, site_hash, None) {
+            Ok((_, document)) => search_documents.lock().unwrap().push(document),
+            Err(err) => evaluator
+                .reporter
+                .error(format!("Failed to link {}: {}", page.slug, err)),
+        }
+    }
+
+    {
+        let project = Arc::clone(&project);
+        let evaluator = Arc::clone(&evaluator);
+        let linked_pages = Arc::clone(&linked_pages);
+        let search_documents = Arc::clone(&search_documents);
+
+        let mut pool = Pool::new(num_cpus as u32);
+        pool.scoped(move |scoped| {
+            for mut entry in pending_pages {
+                let project = Arc::clone(&project);
+                let evaluator = Arc::clone(&evaluator);
+                let renderer = Arc::clone(&renderer);
+                let linked_pages = Arc::clone(&linked_pages);
+                let search_documents = Arc::clone(&search_documents);
+
+                scoped.execute(move || {
+                    match project.link_file(
+                        &evaluator,
+                        &entry.page,
+                        &renderer,
+                        site_hash,
+                        entry.output_hash,
+                    ) {
+                        Ok((output_hash, document)) => {
+                            entry.output_hash = Some(output_hash);
+                            search_documents.lock().unwrap().push(document);
+                            linked_pages.lock().unwrap().push(entry);
+                        }
+                        Err(err) => evaluator.reporter.error(format!(
+                            "Failed to link {}: {}",
+                            entry.page.source_path.to_string_lossy(),
+                            err
+                        )),
+                    }
+                });
+            }
+        });
+    }
+
+    let mut linked_pages = linked_pages.lock().unwrap();
+
+    // Page assets take precedence over theme assets
+    let assets: Vec<_> = linked_pages
+        .iter()
+        .flat_map(|entry| entry.page.assets.iter().cloned())
+        .chain(theme_assets)
+        .collect();
+    copy_assets(&project.output, &assets, &evaluator.reporter);
+
+    let search_documents: Vec<_> = search_documents.lock().unwrap().drain(..).collect();
+    if let Err(err) = search::write(&project.output, search_documents) {
+        evaluator
+            .reporter
+            .error(format!("Failed to write search index: {}", err));
+    }
+
+    if let Err(err) = tabs::write_script(&project.output) {
+        evaluator
+            .reporter
+            .error(format!("Failed to write tabs script: {}", err));
+    }
+
+    if let Some(ref base_url) = project.base_url {
+        let entries: Vec<_> = linked_pages
+            .iter()
+            .map(|entry| &entry.page)
+            .chain(&whats_new_pages)
+            .map(|page| {
+                sitemap::Entry::new(&project.output, &page.slug, base_url, project.pretty_url)
+            })
+            .collect();
+
+        if let Err(err) = sitemap::write(&project.output, base_url, entries) {
+            evaluator
+                .reporter
+                .error(format!("Failed to write sitemap: {}", err));
         }
-    });
+    }
+
+    let mut cache = Cache::new(project.cache_key);
+    for entry in linked_pages.drain(..) {
+        cache.push(entry, &evaluator);
+    }
+
+    if let Err(err) = cache.save(&project.cache_dir) {
+        warn!("Failed to save build cache: {}", err);
+    }
+
+    evaluator.reporter.counts()
 }
 
-fn build(verbose: bool) {
+/// Compile the project and resolve every reference without rendering or writing
+/// anything, returning the number of errors and warnings reported.
+fn check_project(project: Project, evaluator: Evaluator) -> Counts {
+    let project = Arc::new(project);
+    let evaluator = Arc::new(evaluator);
+
+    if let Some((pages, _)) = compile_project(&project, &evaluator) {
+        for entry in &pages {
+            // Unknown references are reported as they're found
+            evaluator.substitute(&entry.page);
+        }
+    }
+
+    evaluator.reporter.counts()
+}
+
+/// Options given on the command line that affect a build or check.
+#[derive(Default)]
+struct BuildOptions {
+    verbose: bool,
+    warnings_as_errors: bool,
+
+    /// Variables defined with -D, which take precedence over config.toml
+    variables: Vec<(String, String)>,
+
+    /// Tags given with -t, which decide what the only directive includes
+    tags: Vec<String>,
+}
+
+fn load_project(options: &BuildOptions) -> (Project, Evaluator) {
     let mut config =
         Project::read_toml(Path::new("config.toml")).expect("Failed to open config.toml");
 
-    config.verbose = verbose;
+    config.verbose = options.verbose;
+
+    if !options.variables.is_empty() || !options.tags.is_empty() {
+        let mut hasher = DefaultHasher::new();
+        config.cache_key.hash(&mut hasher);
+        options.variables.hash(&mut hasher);
+        options.tags.hash(&mut hasher);
+        config.cache_key = hasher.finish();
+
+        for (name, value) in &options.variables {
+            config.variables.insert(name.to_owned(), value.to_owned());
+        }
+    }
 
-    let mut evaluator = Evaluator::new_with_options(config.content_dir.to_owned());
+    let mut evaluator =
+        Evaluator::new_with_options(config.content_dir.to_owned(), config.pretty_url);
+    evaluator.reporter = Reporter::new(options.warnings_as_errors);
+    evaluator.tags = options.tags.iter().cloned().collect();
     evaluator.register_prelude("code", Box::new(directives::Code));
-    evaluator.register_prelude("table", Box::new(directives::Dummy));
-    evaluator.register_prelude("version", Box::new(directives::Version::new("3.4.0")));
+    evaluator.register_prelude("literalinclude", Box::new(directives::LiteralInclude));
+    evaluator.register_prelude("table", Box::new(directives::Table));
     evaluator.register_prelude(
-        "note",
-        Box::new(directives::Admonition::new("Note", "note")),
-    );
-    evaluator.register_prelude(
-        "warning",
-        Box::new(directives::Admonition::new("Warning", "warning")),
+        "version",
+        Box::new(directives::Version::new(&config.version, &config.release)),
     );
+    for &change in &[Change::Added, Change::Changed, Change::Deprecated] {
+        let name = match change {
+            Change::Deprecated => "deprecated".to_owned(),
+            _ => format!("version{}", change.name()),
+        };
+        let project_version = directives::Version::new(&config.version, &config.release);
+        evaluator.register_prelude(
+            name,
+            Box::new(VersionChange::new(change, project_version, config.deprecation_window)),
+        );
+    }
     evaluator.register_prelude("define-template", Box::new(directives::DefineTemplate));
     evaluator.register_prelude("definition-list", Box::new(directives::DefinitionList));
     evaluator.register_prelude("concat", Box::new(directives::Concat));
@@ -329,12 +803,15 @@ fn build(verbose: bool) {
     evaluator.register_prelude("define-program", Box::new(directives::RefDefDirective::new("program")));
     evaluator.register_prelude("program", Box::new(directives::RefDirective::new("program")));
     evaluator.register_prelude("link", Box::new(directives::Link));
+    evaluator.register_prelude("footnote", Box::new(directives::Footnote));
+    evaluator.register_prelude("footnote-ref", Box::new(directives::FootnoteRef));
     evaluator.register_prelude("figure", Box::new(directives::Figure));
     evaluator.register_prelude("ul", Box::new(directives::List::new("ul")));
     evaluator.register_prelude("ol", Box::new(directives::List::new("ol")));
 
     // Structural
     evaluator.register_prelude("glossary", Box::new(glossary::Glossary));
+    evaluator.register_prelude("tabs", Box::new(tabs::Tabs));
     evaluator.register_prelude("steps", Box::new(directives::Steps));
 
     // Formatting
@@ -355,40 +832,146 @@ fn build(verbose: bool) {
     evaluator.register_prelude("not", Box::new(logic::Not));
     evaluator.register_prelude("=", Box::new(logic::Equals));
     evaluator.register_prelude("!=", Box::new(logic::NotEquals));
+    evaluator.register_prelude("only", Box::new(logic::Only));
 
-    let start_time = time::precise_time_ns();
-    build_project(config, evaluator);
+    // Variables must not shadow directives
+    for (name, admonition) in &config.admonitions {
+        if evaluator.is_defined(name) {
+            warn!("Ignoring admonition '{}', which has the same name as a directive", name);
+            continue;
+        }
+
+        evaluator.register_prelude(
+            name.to_owned(),
+            Box::new(directives::Admonition::from_config(name, admonition)),
+        );
+    }
 
+    let mut variables: Vec<_> = config.variables.iter().collect();
+    variables.sort();
+    for (name, value) in variables {
+        if !evaluator.register_variable(name, value) {
+            warn!("Ignoring variable '{}', which has the same name as a directive", name);
+        }
+    }
+
+    (config, evaluator)
+}
+
+/// Log a summary of a build or check, returning false if any errors were reported.
+fn summarize(action: &str, counts: Counts, start_time: u64) -> bool {
     info!(
         "Took {} seconds",
         (time::precise_time_ns() - start_time) as f64 / (f64::from(1_000_000_000))
     );
+
+    if counts.errors > 0 {
+        error!("{} failed with {}", action, counts);
+    } else if counts.warnings > 0 {
+        warn!("{} finished with {}", action, counts);
+    }
+
+    counts.errors == 0
+}
+
+/// Build the project, returning false if any errors were reported.
+fn build(options: &BuildOptions) -> bool {
+    let (config, evaluator) = load_project(options);
+
+    let start_time = time::precise_time_ns();
+    let counts = build_project(config, evaluator);
+    summarize("Build", counts, start_time)
+}
+
+/// Check the project for problems, returning false if any errors were reported.
+fn check(options: &BuildOptions) -> bool {
+    let (config, evaluator) = load_project(options);
+
+    let start_time = time::precise_time_ns();
+    let counts = check_project(config, evaluator);
+    summarize("Check", counts, start_time)
+}
+
+/// Build and serve the project, rebuilding with the given options whenever it changes.
+fn serve(options: BuildOptions, port: u16) {
+    let config =
+        Project::read_toml(Path::new("config.toml")).expect("Failed to open config.toml");
+
+    let mut watch = vec![
+        PathBuf::from("config.toml"),
+        config.content_dir.to_owned(),
+        config.theme.to_owned(),
+    ];
+    if let Some(theme_dir) = config.theme.parent() {
+        if theme_dir != Path::new("") {
+            watch.push(theme_dir.to_owned());
+        }
+    }
+
+    let rebuild = move || {
+        build(&options);
+    };
+
+    if let Err(err) = serve::serve(port, config.output, watch, rebuild) {
+        error!("Failed to start server: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Parse a -D argument of the form name=value.
+fn parse_definition(definition: &str) -> Option<(String, String)> {
+    let mut parts = definition.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => {
+            Some((name.to_owned(), value.to_owned()))
+        }
+        _ => None,
+    }
 }
 
 const DESCRIPTION_BUILD: &str =
     "Build the Rocket project in the current working directory.";
+const DESCRIPTION_CHECK: &str =
+    "Check the Rocket project in the current working directory for problems without writing any output.";
 const DESCRIPTION_NEW: &str = "Create an empty Rocket project.";
+const DESCRIPTION_SERVE: &str =
+    "Build and serve the Rocket project in the current working directory, rebuilding on change.";
 const HELP_VERBOSE: &str = "Increase logging verbosity.";
+const HELP_WARNINGS_AS_ERRORS: &str = "Treat warnings as errors, failing the build or check.";
+const HELP_TAG: &str = "Add a tag, which the only directive can test for.";
+const HELP_DEFINE: &str = "Define a variable, overriding any value given in config.toml.";
+const NOTE_SERVE_CONFIG: &str = "Every rebuild reads config.toml again, but the server keeps \
+    watching and serving the content_dir, theme, and output that it started with. Restart it \
+    after changing those.";
+const DEFAULT_PORT: u16 = 8000;
 
 enum ArgMode {
     Root,
     New,
     Build,
+    Check,
+    Serve,
 }
 
 fn main() {
-    let args = env::args().skip(1);
+    let mut args = env::args().skip(1);
     let mut verbose = false;
+    let mut warnings_as_errors = false;
+    let mut variables = vec![];
+    let mut tags = vec![];
+    let mut port = DEFAULT_PORT;
     let mut new_name: Option<String> = None;
     let mut mode = ArgMode::Root;
 
     let help = |code| -> ! {
-        println!("Usage:\n  rocket [-h, OPTS...] {{ new | build }} ...\n");
+        println!("Usage:\n  rocket [-h, OPTS...] {{ new | build | check | serve }} ...\n");
         println!("Description:\n  The Rocket documentation build system.\n");
         println!(
-            "Subcommands:\n  new\n    {}\n  build\n    {}\n",
+            "Subcommands:\n  new\n    {}\n  build\n    {}\n  check\n    {}\n  serve\n    {}\n",
             DESCRIPTION_NEW,
-            DESCRIPTION_BUILD
+            DESCRIPTION_BUILD,
+            DESCRIPTION_CHECK,
+            DESCRIPTION_SERVE
         );
         println!("Optional arguments:");
         println!("  --help, -h\n    Print this message and exit.\n");
@@ -402,11 +985,42 @@ fn main() {
         println!("Description:\n  {}\n", DESCRIPTION_BUILD);
         println!("Optional arguments:");
         println!("  --verbose, -v\n    {}\n", HELP_VERBOSE);
+        println!("  --warnings-as-errors\n    {}\n", HELP_WARNINGS_AS_ERRORS);
+        println!("  --tag, -t <tag>\n    {}\n", HELP_TAG);
+        println!("  -D <name>=<value>\n    {}\n", HELP_DEFINE);
         println!("  --help, -h\n    Print this message and exit.\n");
 
         process::exit(code);
     };
 
+    let help_check = |code| -> ! {
+        println!("Usage:\n  rocket check [-h, OPTS...]\n");
+        println!("Description:\n  {}\n", DESCRIPTION_CHECK);
+        println!("Optional arguments:");
+        println!("  --verbose, -v\n    {}\n", HELP_VERBOSE);
+        println!("  --warnings-as-errors\n    {}\n", HELP_WARNINGS_AS_ERRORS);
+        println!("  --tag, -t <tag>\n    {}\n", HELP_TAG);
+        println!("  -D <name>=<value>\n    {}\n", HELP_DEFINE);
+        println!("  --help, -h\n    Print this message and exit.\n");
+
+        process::exit(code);
+    };
+
+    let help_serve = |code| -> ! {
+        println!("Usage:\n  rocket serve [-h, OPTS...]\n");
+        println!("Description:\n  {}\n", DESCRIPTION_SERVE);
+        println!("Optional arguments:");
+        println!("  --port, -p <port>\n    The port to listen on. Defaults to {}.\n", DEFAULT_PORT);
+        println!("  --verbose, -v\n    {}\n", HELP_VERBOSE);
+        println!("  --warnings-as-errors\n    {}\n", HELP_WARNINGS_AS_ERRORS);
+        println!("  --tag, -t <tag>\n    {}\n", HELP_TAG);
+        println!("  -D <name>=<value>\n    {}\n", HELP_DEFINE);
+        println!("  --help, -h\n    Print this message and exit.\n");
+        println!("{}\n", NOTE_SERVE_CONFIG);
+
+        process::exit(code);
+    };
+
     let help_new = |code| -> ! {
         println!("Usage:\n  rocket new [-h, OPTS...] name\n");
         println!("Description:\n  {}\n", DESCRIPTION_NEW);
@@ -418,7 +1032,7 @@ fn main() {
         process::exit(code);
     };
 
-    for arg in args {
+    while let Some(arg) = args.next() {
         match mode {
             ArgMode::Root => match arg.as_ref() {
                 "-h" | "--help" => help(0),
@@ -432,7 +1046,9 @@ fn main() {
                 }
                 "-v" | "--verbose" => verbose = true,
                 "build" => mode = ArgMode::Build,
+                "check" => mode = ArgMode::Check,
                 "new" => mode = ArgMode::New,
+                "serve" => mode = ArgMode::Serve,
                 _ => help(1),
             },
             ArgMode::New => {
@@ -447,8 +1063,41 @@ fn main() {
             ArgMode::Build => match arg.as_ref() {
                 "-h" | "--help" => help_build(0),
                 "-v" | "--verbose" => verbose = true,
+                "--warnings-as-errors" => warnings_as_errors = true,
+                "-D" => match args.next().as_ref().and_then(|d| parse_definition(d)) {
+                    Some(definition) => variables.push(definition),
+                    None => help_build(1),
+                },
+                "-t" | "--tag" => tags.push(args.next().unwrap_or_else(|| help_build(1))),
                 _ => help_build(1),
             },
+            ArgMode::Check => match arg.as_ref() {
+                "-h" | "--help" => help_check(0),
+                "-v" | "--verbose" => verbose = true,
+                "--warnings-as-errors" => warnings_as_errors = true,
+                "-D" => match args.next().as_ref().and_then(|d| parse_definition(d)) {
+                    Some(definition) => variables.push(definition),
+                    None => help_check(1),
+                },
+                "-t" | "--tag" => tags.push(args.next().unwrap_or_else(|| help_check(1))),
+                _ => help_check(1),
+            },
+            ArgMode::Serve => match arg.as_ref() {
+                "-h" | "--help" => help_serve(0),
+                "-v" | "--verbose" => verbose = true,
+                "-p" | "--port" => {
+                    port = args.next()
+                        .and_then(|p| p.parse::<u16>().ok())
+                        .unwrap_or_else(|| help_serve(1));
+                }
+                "--warnings-as-errors" => warnings_as_errors = true,
+                "-D" => match args.next().as_ref().and_then(|d| parse_definition(d)) {
+                    Some(definition) => variables.push(definition),
+                    None => help_serve(1),
+                },
+                "-t" | "--tag" => tags.push(args.next().unwrap_or_else(|| help_serve(1))),
+                _ => help_serve(1),
+            },
         }
     }
 
@@ -460,9 +1109,22 @@ fn main() {
 
     simple_logger::init_with_level(loglevel).expect("Failed to initialize logger");
 
+    let options = BuildOptions {
+        verbose,
+        warnings_as_errors,
+        variables,
+        tags,
+    };
+
     match mode {
         ArgMode::Root => help(1),
         ArgMode::New => init::init(&new_name.unwrap_or_else(|| help_new(1))),
-        ArgMode::Build => build(verbose),
+        ArgMode::Build => if !build(&options) {
+            process::exit(1);
+        },
+        ArgMode::Check => if !check(&options) {
+            process::exit(1);
+        },
+        ArgMode::Serve => serve(options, port),
     }
 }
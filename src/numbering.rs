@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use page::Slug;
+
+/// A kind of content that is numbered automatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Counter {
+    Figure,
+    Table,
+}
+
+impl Counter {
+    pub fn label(self) -> &'static str {
+        match self {
+            Counter::Figure => "Figure",
+            Counter::Table => "Table",
+        }
+    }
+}
+
+/// Whether numbers start over on each page, or on each chapter of the root toctree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    #[default]
+    Page,
+    Chapter,
+}
+
+/// Where a page's numbers start within its chapter.
+struct Position {
+    chapter: usize,
+    offsets: HashMap<Counter, usize>,
+}
+
+/// Turns the numbers that each page gives its figures and tables into the numbers
+/// that readers see. Pages outside of any chapter are numbered on their own.
+#[derive(Default)]
+pub struct Numbering {
+    positions: HashMap<Slug, Position>,
+}
+
+impl Numbering {
+    /// Number content across each chapter, given every page in reading order along
+    /// with its chapter, and how many of each counter every page used.
+    pub fn new(
+        scope: Scope,
+        chapters: &[(&Slug, Option<usize>)],
+        counts: &HashMap<&Slug, &[(Counter, usize)]>,
+    ) -> Self {
+        let mut numbering = Numbering::default();
+        if scope == Scope::Page {
+            return numbering;
+        }
+
+        let mut totals: HashMap<(usize, Counter), usize> = HashMap::new();
+        for &(slug, chapter) in chapters {
+            let chapter = match chapter {
+                Some(chapter) => chapter,
+                None => continue,
+            };
+
+            let mut offsets = HashMap::new();
+            for &(counter, n) in counts.get(slug).cloned().unwrap_or_default() {
+                let total = totals.entry((chapter, counter)).or_insert(0);
+                offsets.insert(counter, *total);
+                *total += n;
+            }
+
+            numbering
+                .positions
+                .insert(slug.to_owned(), Position { chapter, offsets });
+        }
+
+        numbering
+    }
+
+    /// The label of the nth use of counter on the given page, such as "Figure 2.3".
+    pub fn format(&self, slug: &Slug, counter: Counter, n: usize) -> String {
+        match self.positions.get(slug) {
+            Some(position) => {
+                let offset = position.offsets.get(&counter).cloned().unwrap_or(0);
+                format!("{} {}.{}", counter.label(), position.chapter, offset + n)
+            }
+            None => format!("{} {}", counter.label(), n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slug(s: &str) -> Slug {
+        Slug::new(s.to_owned())
+    }
+
+    #[test]
+    fn test_numbering() {
+        let (index, intro, install, api) =
+            (slug("index"), slug("intro"), slug("intro/install"), slug("api"));
+        let chapters = vec![
+            (&index, None),
+            (&intro, Some(1)),
+            (&install, Some(1)),
+            (&api, Some(2)),
+        ];
+
+        let mut counts = HashMap::new();
+        let two_figures = [(Counter::Figure, 2)];
+        let mixed = [(Counter::Figure, 1), (Counter::Table, 1)];
+        counts.insert(&index, &two_figures[..]);
+        counts.insert(&intro, &two_figures[..]);
+        counts.insert(&install, &mixed[..]);
+        counts.insert(&api, &mixed[..]);
+
+        let numbering = Numbering::new(Scope::Page, &chapters, &counts);
+        assert_eq!(numbering.format(&install, Counter::Figure, 1), "Figure 1");
+        assert_eq!(numbering.format(&api, Counter::Table, 1), "Table 1");
+
+        let numbering = Numbering::new(Scope::Chapter, &chapters, &counts);
+        assert_eq!(numbering.format(&index, Counter::Figure, 2), "Figure 2");
+        assert_eq!(numbering.format(&intro, Counter::Figure, 2), "Figure 1.2");
+        assert_eq!(numbering.format(&install, Counter::Figure, 1), "Figure 1.3");
+        assert_eq!(numbering.format(&install, Counter::Table, 1), "Table 1.1");
+        assert_eq!(numbering.format(&api, Counter::Figure, 1), "Figure 2.1");
+        assert_eq!(numbering.format(&slug("orphan"), Counter::Table, 1), "Table 1");
+    }
+}
@@ -3,7 +3,7 @@ use std::fmt;
 use std::path::{Path, PathBuf};
 use serde_json::{self, Value};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Slug {
     slug: String,
 }
@@ -32,6 +32,32 @@ impl Slug {
         self.slug.matches('/').count() + modifier
     }
 
+    /// The URL of this page relative to the output root.
+    pub fn url(&self, pretty_url: bool) -> String {
+        if !pretty_url {
+            return format!("{}.html", self.slug);
+        }
+
+        if self.slug == "index" {
+            "".to_owned()
+        } else {
+            self.slug.to_owned()
+        }
+    }
+
+    /// The absolute URL of this page, given the URL of the output root. With pretty
+    /// URLs this is the directory that the page is written into.
+    pub fn canonical_url(&self, base_url: &str, pretty_url: bool) -> String {
+        let output_path = self.create_output_path(Path::new(""), pretty_url);
+        let mut path = output_path.to_string_lossy().replace('\\', "/");
+        if pretty_url && path.ends_with("index.html") {
+            let len = path.len() - "index.html".len();
+            path.truncate(len);
+        }
+
+        format!("{}/{}", base_url.trim_end_matches('/'), path)
+    }
+
     pub fn path_to(&self, dest: &str, pretty_url: bool) -> String {
         let slug_prefix = "../".repeat(self.depth(pretty_url));
         format!("{}{}", slug_prefix, dest)
@@ -50,11 +76,22 @@ impl fmt::Display for Slug {
     }
 }
 
+/// A file that must be copied into the output's static directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Asset {
+    pub source_path: PathBuf,
+
+    /// The path of this asset relative to the static directory
+    pub output_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Page {
     pub source_path: PathBuf,
     pub slug: Slug,
     pub body: String,
     pub theme_config: serde_json::map::Map<String, Value>,
+    pub assets: Vec<Asset>,
 }
 
 impl Page {
@@ -73,3 +110,31 @@ impl Page {
         "Untitled".to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_url() {
+        let slug = Slug::new("tutorials/install".to_owned());
+        let index = Slug::new("index".to_owned());
+
+        assert_eq!(
+            slug.canonical_url("https://example.com/docs/", true),
+            "https://example.com/docs/tutorials/install/"
+        );
+        assert_eq!(
+            slug.canonical_url("https://example.com/docs", false),
+            "https://example.com/docs/tutorials/install.html"
+        );
+        assert_eq!(
+            index.canonical_url("https://example.com", true),
+            "https://example.com/"
+        );
+        assert_eq!(
+            index.canonical_url("https://example.com", false),
+            "https://example.com/index.html"
+        );
+    }
+}
@@ -5,14 +5,15 @@ use std::path::{Path, PathBuf};
 use std::str;
 use regex::Regex;
 
-use lex::{lex, Token};
+use diagnostic::{Diagnostic, ErrorCode};
+use lex::{lex, Position, Span, Token, TokenKind};
 
 lazy_static! {
     static ref PAT_IS_WHITESPACE: Regex =
         Regex::new(r#"^\s+$"#).expect("Failed to compile whitespace regex");
 }
 
-type FileID = u32;
+pub type FileID = u32;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum NodeValue {
@@ -24,31 +25,31 @@ pub enum NodeValue {
 pub struct Node {
     pub value: NodeValue,
     pub file_id: FileID,
-    pub lineno: i32,
+    pub span: Span,
 }
 
 impl Node {
-    pub fn new(value: NodeValue, file_id: FileID, lineno: i32) -> Self {
+    pub fn new(value: NodeValue, file_id: FileID, span: Span) -> Self {
         Node {
             value,
             file_id,
-            lineno,
+            span,
         }
     }
 
-    pub fn new_children(value: Vec<Node>, file_id: FileID, lineno: i32) -> Self {
+    pub fn new_children(value: Vec<Node>, file_id: FileID, span: Span) -> Self {
         Node {
             value: NodeValue::Children(value),
             file_id,
-            lineno,
+            span,
         }
     }
 
-    pub fn new_string<S: Into<String>>(value: S, file_id: FileID, lineno: i32) -> Self {
+    pub fn new_string<S: Into<String>>(value: S, file_id: FileID, span: Span) -> Self {
         Node {
             value: NodeValue::Owned(value.into()),
             file_id,
-            lineno,
+            span,
         }
     }
 
@@ -72,165 +73,237 @@ enum StackRequest {
     None,
     Pop(u8),
     Push(Box<TokenHandler>),
+
+    /// Close this handler as unterminated, and pass the token on to its parent
+    Unwind,
 }
 
 trait TokenHandler {
-    fn handle_token(&mut self, token: &Token) -> StackRequest;
+    fn handle_token(&mut self, token: &Token, diagnostics: &mut Vec<Diagnostic>) -> StackRequest;
     fn finish(&mut self) -> Node;
     fn push(&mut self, node: Node);
-    fn name(&self) -> &'static str;
+
+    /// Describe this handler having been left open when its enclosing context
+    /// ended at the point described by end.
+    fn unterminated(&self, end: &str) -> Option<Diagnostic>;
 }
 
 struct StateRocket {
     root: Vec<Node>,
-    buffer: Vec<String>,
+    buffer: String,
+    buffer_span: Option<Span>,
     file_id: FileID,
-    lineno: i32,
+    span: Span,
+    rocket_span: Span,
+
+    // A => in running text still opens an indented block in the lexer, so we
+    // need to skip over its matching dedent.
+    text_rockets: usize,
+    paren_depth: usize,
+    after_block: bool,
 }
 
 impl StateRocket {
-    fn new(file_id: FileID, lineno: i32) -> Self {
+    fn new(file_id: FileID, span: Span) -> Self {
         StateRocket {
-            root: vec![Node::new_string("concat", file_id, lineno)],
-            buffer: vec![],
-            file_id: file_id,
-            lineno,
+            root: vec![Node::new_string("concat", file_id, span)],
+            buffer: String::new(),
+            buffer_span: None,
+            file_id,
+            span,
+            rocket_span: span,
+            text_rockets: 0,
+            paren_depth: 0,
+            after_block: false,
         }
     }
 
-    fn ensure_string(&mut self) -> &mut String {
-        if self.buffer.is_empty() {
-            self.buffer.push(String::with_capacity(2));
-        }
+    fn append(&mut self, text: &str, span: &Span) {
+        self.buffer.push_str(text);
+        self.buffer_span = match self.buffer_span {
+            Some(buffer_span) => Some(buffer_span.to(span)),
+            None => Some(*span),
+        };
+    }
 
-        self.buffer.last_mut().unwrap()
+    fn flush(&mut self) {
+        if let Some(span) = self.buffer_span.take() {
+            let node = Node::new_string(self.buffer.clone(), self.file_id, span);
+            self.root.push(node);
+            self.buffer.clear();
+        }
     }
 }
 
 impl TokenHandler for StateRocket {
-    fn handle_token(&mut self, token: &Token) -> StackRequest {
-        match *token {
-            Token::Text(_, s) => {
-                self.buffer.push(s.to_owned());
+    fn handle_token(&mut self, token: &Token, diagnostics: &mut Vec<Diagnostic>) -> StackRequest {
+        self.span.end = token.span.end;
+
+        match token.kind {
+            TokenKind::Text(s) => {
+                if s == "(" {
+                    self.paren_depth += 1;
+                }
+
+                if !PAT_IS_WHITESPACE.is_match(s) {
+                    self.after_block = false;
+                }
+
+                self.append(s, &token.span);
+                return StackRequest::None;
             }
-            Token::Quote(_) => {
-                self.ensure_string().push('"');
+            TokenKind::Quote => self.append("\"", &token.span),
+            TokenKind::StartBlock => {
+                self.flush();
+                return StackRequest::Push(Box::new(StateExpression::new(self.file_id, token.span)));
             }
-            Token::StartBlock(lineno) => {
-                if !self.buffer.is_empty() {
-                    self.root
-                        .push(Node::new_string(self.buffer.concat(), self.file_id, lineno));
-                    self.buffer.clear();
+            TokenKind::RightParen => {
+                if self.paren_depth > 0 {
+                    self.paren_depth -= 1;
+                } else if self.after_block {
+                    // Parentheses are common enough in prose that we only complain
+                    // about one that immediately follows a block.
+                    diagnostics.push(
+                        Diagnostic::warning(ErrorCode::SyntaxError, "unmatched `)`")
+                            .at_span(self.file_id, token.span)
+                            .with_note("this `)` does not close any block, and will appear in the output"),
+                    );
                 }
-                return StackRequest::Push(Box::new(StateExpression::new(self.file_id, lineno)));
-            }
-            Token::RightParen => {
-                self.ensure_string().push(')');
+                self.append(")", &token.span);
             }
-            Token::Rocket(_) => {
-                self.ensure_string().push_str("=>");
+            TokenKind::Rocket => {
+                self.text_rockets += 1;
+                self.append("=>", &token.span);
             }
-            Token::Dedent => {
+            TokenKind::Dedent => {
+                if self.text_rockets > 0 {
+                    self.text_rockets -= 1;
+                    return StackRequest::None;
+                }
+
                 // We need to pop both the rocket and the expression that started the rocket
                 return StackRequest::Pop(2);
             }
         }
 
+        self.after_block = false;
         StackRequest::None
     }
 
     fn finish(&mut self) -> Node {
-        if !self.buffer.is_empty() {
-            let string = self.buffer.concat();
-            self.root
-                .push(Node::new_string(string, self.file_id, self.lineno));
-        }
-
+        self.flush();
         Node::new_children(
             mem::replace(&mut self.root, vec![]),
             self.file_id,
-            self.lineno,
+            self.span,
         )
     }
 
     fn push(&mut self, node: Node) {
+        self.span.end = node.span.end;
+        self.after_block = true;
         self.root.push(node);
     }
 
-    fn name(&self) -> &'static str {
-        "rocket"
+    fn unterminated(&self, end: &str) -> Option<Diagnostic> {
+        Some(
+            Diagnostic::error(ErrorCode::SyntaxError, "unterminated `=>` block")
+                .at_span(self.file_id, self.rocket_span)
+                .with_note(format!("expected the indented block to end before {}", end)),
+        )
     }
 }
 
 struct StateExpression {
     root: Vec<Node>,
     file_id: FileID,
-    lineno: i32,
+    span: Span,
+    block_span: Span,
 
     quote: String,
+    quote_span: Span,
     quote_should_merge: bool,
+    quote_rockets: usize,
     in_quote: bool,
     new_node: bool,
 }
 
 impl StateExpression {
-    fn new(file_id: FileID, lineno: i32) -> Self {
+    fn new(file_id: FileID, span: Span) -> Self {
         StateExpression {
             root: vec![],
             file_id,
-            lineno,
+            span,
+            block_span: span,
             quote: String::new(),
+            quote_span: span,
             quote_should_merge: false,
+            quote_rockets: 0,
             in_quote: false,
             new_node: true,
         }
     }
+
+    fn close_quote(&mut self, end: Position) {
+        let should_add_node = if self.quote_should_merge {
+            if let Some(node) = self.root.last_mut() {
+                match node.value {
+                    NodeValue::Owned(ref mut s) => {
+                        s.push_str(&self.quote);
+                        node.span.end = end;
+                        false
+                    }
+                    _ => true,
+                }
+            } else {
+                true
+            }
+        } else {
+            true
+        };
+
+        if should_add_node {
+            self.root.push(Node::new_string(
+                self.quote.to_owned(),
+                self.file_id,
+                Span::new(self.quote_span.start, end),
+            ));
+        }
+
+        self.quote_should_merge = false;
+        self.in_quote = false;
+        self.quote.clear();
+    }
 }
 
 impl TokenHandler for StateExpression {
-    fn handle_token(&mut self, token: &Token) -> StackRequest {
+    fn handle_token(&mut self, token: &Token, _: &mut Vec<Diagnostic>) -> StackRequest {
+        self.span.end = token.span.end;
+
         if self.in_quote {
-            match *token {
-                Token::Text(_, s) => self.quote.push_str(s),
-                Token::Quote(lineno) => {
-                    let should_add_node = if self.quote_should_merge {
-                        if let Some(node) = self.root.last_mut() {
-                            match node.value {
-                                NodeValue::Owned(ref mut s) => {
-                                    s.push_str(&self.quote);
-                                    false
-                                }
-                                _ => true,
-                            }
-                        } else {
-                            true
-                        }
-                    } else {
-                        true
-                    };
-
-                    if should_add_node {
-                        self.root.push(Node::new_string(
-                            self.quote.to_owned(),
-                            self.file_id,
-                            lineno,
-                        ));
+            match token.kind {
+                TokenKind::Text(s) => self.quote.push_str(s),
+                TokenKind::Quote => self.close_quote(token.span.end),
+                TokenKind::StartBlock => self.quote.push_str("(:"),
+                TokenKind::RightParen => self.quote.push(')'),
+                TokenKind::Rocket => {
+                    self.quote_rockets += 1;
+                    self.quote.push_str("=>");
+                }
+                TokenKind::Dedent => {
+                    if self.quote_rockets == 0 {
+                        // The indented block containing this quote has ended
+                        return StackRequest::Unwind;
                     }
 
-                    self.quote_should_merge = false;
-                    self.in_quote = false;
-                    self.quote.clear();
+                    self.quote_rockets -= 1;
                 }
-                Token::StartBlock(_) => self.quote.push_str("(:"),
-                Token::RightParen => self.quote.push(')'),
-                Token::Rocket(_) => self.quote.push_str("=>"),
-                Token::Dedent => (),
             }
             return StackRequest::None;
         }
 
-        match *token {
-            Token::Text(lineno, s) => {
+        match token.kind {
+            TokenKind::Text(s) => {
                 // When in an expression, whitespace only serves to separate tokens.
                 if PAT_IS_WHITESPACE.is_match(s) {
                     self.new_node = true;
@@ -242,7 +315,10 @@ impl TokenHandler for StateExpression {
                     if !new_node {
                         if let Some(last) = self.root.last_mut() {
                             match last.value {
-                                NodeValue::Owned(ref mut val) => val.push_str(s),
+                                NodeValue::Owned(ref mut val) => {
+                                    val.push_str(s);
+                                    last.span.end = token.span.end;
+                                }
                                 NodeValue::Children(_) => new_node = true,
                             }
                         } else {
@@ -252,80 +328,155 @@ impl TokenHandler for StateExpression {
 
                     if new_node {
                         self.root
-                            .push(Node::new_string(s.to_owned(), self.file_id, lineno));
+                            .push(Node::new_string(s.to_owned(), self.file_id, token.span));
                     }
                     self.new_node = false;
                 }
             }
-            Token::Quote(_) => self.in_quote = true,
-            Token::StartBlock(lineno) => {
-                return StackRequest::Push(Box::new(StateExpression::new(self.file_id, lineno)));
+            TokenKind::Quote => {
+                self.in_quote = true;
+                self.quote_span = token.span;
+            }
+            TokenKind::StartBlock => {
+                return StackRequest::Push(Box::new(StateExpression::new(self.file_id, token.span)));
             }
-            Token::Rocket(lineno) => {
-                return StackRequest::Push(Box::new(StateRocket::new(self.file_id, lineno)));
+            TokenKind::Rocket => {
+                return StackRequest::Push(Box::new(StateRocket::new(self.file_id, token.span)));
             }
-            Token::RightParen | Token::Dedent => {
+            TokenKind::RightParen => {
                 return StackRequest::Pop(1);
             }
+            TokenKind::Dedent => {
+                // The indented block containing this expression has ended
+                return StackRequest::Unwind;
+            }
         }
 
         StackRequest::None
     }
 
     fn finish(&mut self) -> Node {
+        if self.in_quote {
+            let end = self.span.end;
+            self.close_quote(end);
+        }
+
         Node::new_children(
             mem::replace(&mut self.root, vec![]),
             self.file_id,
-            self.lineno,
+            self.span,
         )
     }
 
     fn push(&mut self, node: Node) {
+        self.span.end = node.span.end;
         self.root.push(node);
     }
 
-    fn name(&self) -> &'static str {
+    fn unterminated(&self, end: &str) -> Option<Diagnostic> {
         if self.in_quote {
-            "expression-quote"
-        } else {
-            "expression"
+            return Some(
+                Diagnostic::error(ErrorCode::SyntaxError, "unterminated string")
+                    .at_span(self.file_id, self.quote_span)
+                    .with_note(format!("expected a closing `\"` before {}", end)),
+            );
         }
+
+        // Point at the opening of the block along with its directive name, if any
+        let (name, span) = match self.root.first() {
+            Some(&Node {
+                value: NodeValue::Owned(ref name),
+                span,
+                ..
+            }) => (name.as_str(), self.block_span.to(&span)),
+            _ => ("", self.block_span),
+        };
+
+        Some(
+            Diagnostic::error(
+                ErrorCode::SyntaxError,
+                format!("unterminated `(:{}` block", name),
+            ).at_span(self.file_id, span)
+                .with_note(format!("expected `)` before {}", end)),
+        )
     }
 }
 
 struct ParseContextStack {
     stack: Vec<Box<TokenHandler>>,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl ParseContextStack {
-    fn new(file_id: FileID, lineno: i32) -> Self {
+    fn new(file_id: FileID) -> Self {
+        let start = Position::new(0, 0, 0);
         ParseContextStack {
-            stack: vec![
-                Box::new(StateRocket {
-                    root: vec![Node::new_string("concat", file_id, lineno)],
-                    buffer: vec![],
-                    file_id: file_id,
-                    lineno: lineno,
-                }),
-            ],
+            stack: vec![Box::new(StateRocket::new(file_id, Span::new(start, start)))],
+            diagnostics: vec![],
         }
     }
 
     fn handle(&mut self, token: &Token) {
-        match self.stack
-            .last_mut()
+        loop {
+            match self.stack
+                .last_mut()
+                .expect("Empty parse stack")
+                .handle_token(token, &mut self.diagnostics)
+            {
+                StackRequest::Push(handler) => {
+                    self.stack.push(handler);
+                }
+                StackRequest::Pop(n) => for _ in 0..n {
+                    self.pop();
+                },
+                StackRequest::Unwind if self.stack.len() > 1 => {
+                    let end = format!(
+                        "the end of the indented block on line {}",
+                        token.span.lineno() + 1
+                    );
+                    self.unwind(&end);
+
+                    // Give the token to whichever handler is now on top
+                    continue;
+                }
+                StackRequest::Unwind | StackRequest::None => (),
+            }
+
+            return;
+        }
+    }
+
+    fn pop(&mut self) {
+        // A stray token can't close the root; it's simply ignored
+        if self.stack.len() < 2 {
+            return;
+        }
+
+        let mut handler = self.stack.pop().expect("Empty parse stack");
+        (**self.stack.last_mut().expect("Empty parse stack")).push(handler.finish());
+    }
+
+    fn unwind(&mut self, end: &str) {
+        if let Some(diagnostic) = self.stack
+            .last()
             .expect("Empty parse stack")
-            .handle_token(token)
+            .unterminated(end)
         {
-            StackRequest::Push(handler) => {
-                self.stack.push(handler);
-            }
-            StackRequest::Pop(n) => for _ in 0..n {
-                let mut handler = self.stack.pop().expect("Cannot pop last handler");
-                (**self.stack.last_mut().expect("Empty parse stack")).push(handler.finish());
-            },
-            StackRequest::None => (),
+            self.diagnostics.push(diagnostic);
+        }
+
+        self.pop();
+    }
+
+    /// Close any handlers still open at the end of the file, and return the root node
+    /// along with every problem found.
+    fn finish(mut self) -> (Node, Vec<Diagnostic>) {
+        while self.stack.len() > 1 {
+            self.unwind("the end of the file");
         }
+
+        let root = self.stack.pop().expect("Empty parse stack").finish();
+        (root, self.diagnostics)
     }
 }
 
@@ -339,29 +490,39 @@ impl Parser {
     }
 
     pub fn get_node_source_path(&self, node: &Node) -> Option<&Path> {
-        match self.file_ids.get(node.file_id as usize) {
+        self.get_path(node.file_id)
+    }
+
+    pub fn get_path(&self, id: FileID) -> Option<&Path> {
+        match self.file_ids.get(id as usize) {
             Some(p) => Some(p),
             None => None,
         }
     }
 
-    fn parse_string(&mut self, id: FileID, data: String) -> Result<Node, String> {
-        let mut stack = ParseContextStack::new(id, 0);
+    /// The ID that the next parsed file will receive.
+    pub fn next_file_id(&self) -> FileID {
+        self.file_ids.len() as FileID
+    }
+
+    /// Every file parsed since the file with the given ID, inclusive.
+    pub fn get_paths_since(&self, id: FileID) -> &[PathBuf] {
+        &self.file_ids[id as usize..]
+    }
+
+    fn parse_string(&mut self, id: FileID, data: String) -> (Node, Vec<Diagnostic>) {
+        let mut stack = ParseContextStack::new(id);
         for token in lex(&data) {
             stack.handle(&token);
         }
 
-        let root = stack.stack.pop().expect("Empty state stack").finish();
-        match stack.stack.pop() {
-            Some(_) => Err(format!(
-                "Unterminated block started on line {}",
-                root.lineno
-            )),
-            None => Ok(root),
-        }
+        stack.finish()
     }
 
-    pub fn parse(&mut self, path: &Path) -> Result<Node, String> {
+    /// Parse the file at path, returning its syntax tree along with every syntax
+    /// problem found. The parser recovers from errors so that it can report as many
+    /// as possible, but a tree with errors should not be evaluated.
+    pub fn parse(&mut self, path: &Path) -> Result<(Node, Vec<Diagnostic>), String> {
         debug!("Parsing {}", path.to_string_lossy());
 
         let id = self.file_ids.len() as FileID;
@@ -374,29 +535,57 @@ impl Parser {
             }
         };
         let mut data = String::new();
-        file.read_to_string(&mut data)
-            .expect("Failed to read input file");
+        if let Err(err) = file.read_to_string(&mut data) {
+            return Err(format!("Failed to read {}: {}", path.to_string_lossy(), err));
+        }
 
-        self.parse_string(id, data)
+        Ok(self.parse_string(id, data))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{env, fs, process};
+    use diagnostic::Level;
+
+    fn line(lineno: i32) -> Span {
+        let position = Position::new(0, lineno, 0);
+        Span::new(position, position)
+    }
+
+    /// Reduce every span in the tree to its starting line, for brevity.
+    fn lines(node: Node) -> Node {
+        let span = line(node.span.lineno());
+        match node.value {
+            NodeValue::Owned(s) => Node::new_string(s, node.file_id, span),
+            NodeValue::Children(children) => Node::new_children(
+                children.into_iter().map(lines).collect(),
+                node.file_id,
+                span,
+            ),
+        }
+    }
+
+    fn parse_lines(parser: &mut Parser, data: &str) -> Result<Node, Vec<Diagnostic>> {
+        match parser.parse_string(0, data.to_owned()) {
+            (node, ref diagnostics) if diagnostics.is_empty() => Ok(lines(node)),
+            (_, diagnostics) => Err(diagnostics),
+        }
+    }
 
     fn rocket(mut args: Vec<Node>, lineno: i32) -> Node {
-        let mut children = vec![Node::new_string("concat", 0, lineno)];
+        let mut children = vec![Node::new_string("concat", 0, line(lineno))];
         for arg in args.drain(..) {
             children.push(arg);
         }
-        Node::new_children(children, 0, lineno)
+        Node::new_children(children, 0, line(lineno))
     }
 
     #[test]
     fn test_empty() {
         let mut parser = Parser::new();
-        assert_eq!(parser.parse_string(0, "".to_owned()), Ok(rocket(vec![], 0)));
+        assert_eq!(parse_lines(&mut parser, ""), Ok(rocket(vec![], 0)));
     }
 
     #[test]
@@ -404,27 +593,25 @@ mod tests {
         let mut parser = Parser::new();
 
         assert!(
-            parser
-                .parse_string(
-                    0,
-                    r#"(:`` ")
+            parse_lines(
+                &mut parser,
+                r#"(:`` ")
 (:h3 =>
-  "Sally")"#.to_owned()
-                )
-                .is_err()
+  "Sally")"#
+            ).is_err()
         );
 
         assert_eq!(
-            parser.parse_string(0, r#"(:`` f"oo ba"r)"#.to_owned()),
+            parse_lines(&mut parser, r#"(:`` f"oo ba"r)"#),
             Ok(rocket(
                 vec![
                     Node::new_children(
                         vec![
-                            Node::new_string("``", 0, 0),
-                            Node::new_string("foo bar", 0, 0),
+                            Node::new_string("``", 0, line(0)),
+                            Node::new_string("foo bar", 0, line(0)),
                         ],
                         0,
-                        0,
+                        line(0),
                     ),
                 ],
                 0
@@ -456,91 +643,203 @@ Rocket is a fast and powerful text markup format.
 
         let h1 = Node::new_children(
             vec![
-                Node::new_string("h1", 0, 0),
-                Node::new_string("Rocket", 0, 0),
+                Node::new_string("h1", 0, line(0)),
+                Node::new_string("Rocket", 0, line(0)),
             ],
             0,
-            0,
+            line(0),
         );
         let para1 = Node::new_string(
             "\n\nRocket is a fast and powerful text markup format.\n\n",
             0,
-            4,
+            line(0),
         );
         let h2_1 = Node::new_children(
             vec![
-                Node::new_string("h2", 0, 4),
+                Node::new_string("h2", 0, line(4)),
                 Node::new_children(
                     vec![
-                        Node::new_string("ref", 0, 4),
-                        Node::new_string("writing-your-first-project", 0, 4),
-                        Node::new_string("Getting Started", 0, 4),
+                        Node::new_string("ref", 0, line(4)),
+                        Node::new_string("writing-your-first-project", 0, line(4)),
+                        Node::new_string("Getting Started", 0, line(4)),
                     ],
                     0,
-                    4,
+                    line(4),
                 ),
             ],
             0,
-            4,
+            line(4),
         );
         let h2_2 = Node::new_children(
             vec![
-                Node::new_string("h2", 0, 5),
-                Node::new_string("=>Example", 0, 5),
+                Node::new_string("h2", 0, line(5)),
+                Node::new_string("=>Example", 0, line(5)),
             ],
             0,
-            5,
+            line(5),
         );
         let code = Node::new_children(
             vec![
-                Node::new_string("code", 0, 6),
-                Node::new_string("txt", 0, 6),
+                Node::new_string("code", 0, line(6)),
+                Node::new_string("txt", 0, line(6)),
                 rocket(
                     vec![
-                        Node::new_children(vec![Node::new_string("(", 0, 7)], 0, 7),
+                        Node::new_children(vec![Node::new_string("(", 0, line(7))], 0, line(7)),
                         Node::new_string(
                             ":h1 Rocket)\n\nRocket is a fast and powerful text markup format.\n\n",
                             0,
-                            11,
+                            line(7),
                         ),
-                        Node::new_children(vec![Node::new_string("(", 0, 11)], 0, 11),
-                        Node::new_string(":h2 ", 0, 11),
-                        Node::new_children(vec![Node::new_string("(", 0, 11)], 0, 11),
+                        Node::new_children(vec![Node::new_string("(", 0, line(11))], 0, line(11)),
+                        Node::new_string(":h2 ", 0, line(11)),
+                        Node::new_children(vec![Node::new_string("(", 0, line(11))], 0, line(11)),
                         Node::new_string(
                             ":ref writing-your-first-project \"Getting Started\"))\n\n",
                             0,
-                            6,
+                            line(11),
                         ),
                     ],
                     6,
                 ),
             ],
             0,
-            6,
+            line(6),
         );
         let toctree = Node::new_children(
             vec![
-                Node::new_string("toctree", 0, 15),
-                Node::new_string("reference", 0, 16),
-                Node::new_string("tutorials", 0, 17),
+                Node::new_string("toctree", 0, line(15)),
+                Node::new_string("reference", 0, line(16)),
+                Node::new_string("tutorials", 0, line(17)),
             ],
             0,
-            15,
+            line(15),
         );
         let result = rocket(
             vec![
                 h1,
                 para1,
                 h2_1,
-                Node::new_string("\n", 0, 5),
+                Node::new_string("\n", 0, line(4)),
                 h2_2,
-                Node::new_string("\n", 0, 6),
+                Node::new_string("\n", 0, line(5)),
                 code,
-                Node::new_string("\n\n", 0, 15),
+                Node::new_string("\n\n", 0, line(13)),
                 toctree,
             ],
             0,
         );
-        assert_eq!(parser.parse_string(0, src.to_owned()), Ok(result));
+        assert_eq!(parse_lines(&mut parser, src), Ok(result));
+    }
+
+    #[test]
+    fn test_spans() {
+        let mut parser = Parser::new();
+        let span = |start: (usize, i32, u32), end: (usize, i32, u32)| {
+            Span::new(
+                Position::new(start.0, start.1, start.2),
+                Position::new(end.0, end.1, end.2),
+            )
+        };
+
+        let (root, diagnostics) = parser.parse_string(0, "x (:a d\"b c\" =>\n  e\nf".to_owned());
+        assert_eq!(diagnostics, vec![]);
+        let children = match root.value {
+            NodeValue::Children(children) => children,
+            _ => panic!("Expected children"),
+        };
+
+        assert_eq!(children[1], Node::new_string("x ", 0, span((0, 0, 0), (2, 0, 2))));
+
+        let expression = &children[2];
+        assert_eq!(expression.span, span((2, 0, 2), (19, 1, 3)));
+        let args = match expression.value {
+            NodeValue::Children(ref args) => args,
+            _ => panic!("Expected children"),
+        };
+
+        assert_eq!(args[0], Node::new_string("a", 0, span((4, 0, 4), (5, 0, 5))));
+        assert_eq!(args[1], Node::new_string("db c", 0, span((6, 0, 6), (12, 0, 12))));
+        assert_eq!(args[2].span, span((13, 0, 13), (19, 1, 3)));
+        assert_eq!(children[3].span.start, Position::new(19, 1, 3));
+    }
+
+    /// Reduce each diagnostic to its level, message and starting line and column.
+    fn parse_problems(data: &str) -> Vec<(Level, String, i32, u32)> {
+        let mut parser = Parser::new();
+        let (_, diagnostics) = parser.parse_string(0, data.to_owned());
+        diagnostics
+            .into_iter()
+            .map(|d| {
+                let start = d.location.expect("Diagnostic has no location").span.start;
+                (d.level, d.message, start.lineno, start.column)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unterminated() {
+        assert_eq!(
+            parse_problems("(:h1 Title)\n(:ul (:** a)\n\nb"),
+            vec![(Level::Error, "unterminated `(:ul` block".to_owned(), 1, 0)]
+        );
+
+        // The end of an indented block closes anything left open inside it, so we
+        // can keep going and find later problems.
+        let mut parser = Parser::new();
+        let (_, diagnostics) = parser.parse_string(
+            0,
+            "(:note =>\n    (:** bold\n\n(:`` \"x)\n".to_owned(),
+        );
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| (d.message.as_str(), d.location.as_ref().unwrap().span.start.lineno))
+                .collect::<Vec<_>>(),
+            vec![("unterminated `(:**` block", 1), ("unterminated string", 3)]
+        );
+        assert_eq!(
+            diagnostics[0].notes,
+            vec!["expected `)` before the end of the indented block on line 2"]
+        );
+        assert_eq!(
+            diagnostics[1].notes,
+            vec!["expected a closing `\"` before the end of the file"]
+        );
+
+        // A quote left open inside an indented block ends with the block
+        assert_eq!(
+            parse_problems("(:note =>\n    (:** \"bold)\nok (:** fine)"),
+            vec![(Level::Error, "unterminated string".to_owned(), 1, 9)]
+        );
+    }
+
+    #[test]
+    fn test_stray_paren() {
+        assert_eq!(
+            parse_problems("(:note =>\n    body\n)\n(:** x))"),
+            vec![
+                (Level::Warning, "unmatched `)`".to_owned(), 2, 0),
+                (Level::Warning, "unmatched `)`".to_owned(), 3, 7),
+            ]
+        );
+
+        // Parentheses in running text are fine
+        assert_eq!(parse_problems("(see (:ref x)) and 1) (:** a) b"), vec![]);
+
+        // As is a => that doesn't follow an expression
+        assert_eq!(parse_problems("a =>\n  b\n(:** c)\n"), vec![]);
+    }
+
+    #[test]
+    fn test_unreadable() {
+        let path = env::temp_dir().join(format!("rocket-test-unreadable-{}.rocket", process::id()));
+        fs::write(&path, b"\xff\xfe(:h1 Title)").unwrap();
+
+        let mut parser = Parser::new();
+        let message = parser.parse(&path).unwrap_err();
+        assert!(message.starts_with("Failed to read "));
+        assert!(parser.parse(&path.with_extension("missing")).is_err());
+
+        fs::remove_file(&path).unwrap();
     }
 }
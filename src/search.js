@@ -0,0 +1,165 @@
+// Client-side search over the index that Rocket writes to searchindex.json.
+// Rendered by the {{search}} theme helper, which sets data-root to the path of the
+// output root relative to the current page.
+(function() {
+    'use strict';
+
+    var MAX_RESULTS = 10;
+    var PREFIX_PENALTY = 0.5;
+
+    var indexPromise = null;
+
+    function loadIndex(root) {
+        if (!indexPromise) {
+            indexPromise = fetch(root + 'searchindex.json').then(function(response) {
+                if (!response.ok) {
+                    throw new Error('Failed to load search index: ' + response.status);
+                }
+                return response.json();
+            });
+        }
+
+        return indexPromise;
+    }
+
+    // Must agree with tokenize() in search.rs
+    function tokenize(text) {
+        return text.toLowerCase().split(/[^\p{L}\p{N}]+/u).filter(function(word) {
+            return word.length > 1;
+        });
+    }
+
+    function search(index, query) {
+        var queryTerms = tokenize(query);
+        if (queryTerms.length === 0) {
+            return [];
+        }
+
+        var nPages = index.pages.length;
+        var scores = null;
+
+        queryTerms.forEach(function(queryTerm) {
+            // The best score each page gets for this query term. A term matches
+            // exactly, or as a prefix of a longer term so that partial words work.
+            var termScores = {};
+            Object.keys(index.terms).forEach(function(term) {
+                var weight;
+                if (term === queryTerm) {
+                    weight = 1;
+                } else if (term.lastIndexOf(queryTerm, 0) === 0) {
+                    weight = PREFIX_PENALTY;
+                } else {
+                    return;
+                }
+
+                var postings = index.terms[term];
+                var idf = Math.log(1 + nPages / postings.length);
+                postings.forEach(function(posting) {
+                    var score = weight * posting[1] * idf;
+                    termScores[posting[0]] = Math.max(termScores[posting[0]] || 0, score);
+                });
+            });
+
+            // Every query term must match
+            if (scores === null) {
+                scores = termScores;
+            } else {
+                var combined = {};
+                Object.keys(scores).forEach(function(page) {
+                    if (termScores[page] !== undefined) {
+                        combined[page] = scores[page] + termScores[page];
+                    }
+                });
+                scores = combined;
+            }
+        });
+
+        return Object.keys(scores).map(function(page) {
+            return {page: index.pages[page], score: scores[page]};
+        }).sort(function(a, b) {
+            return b.score - a.score;
+        }).slice(0, MAX_RESULTS).map(function(result) {
+            return {page: result.page, heading: bestHeading(result.page, queryTerms)};
+        });
+    }
+
+    // The heading that mentions the most query terms, if any
+    function bestHeading(page, queryTerms) {
+        var best = null;
+        var bestCount = 0;
+
+        page.headings.forEach(function(heading) {
+            var headingTerms = tokenize(heading.title);
+            var count = queryTerms.filter(function(queryTerm) {
+                return headingTerms.some(function(term) {
+                    return term.lastIndexOf(queryTerm, 0) === 0;
+                });
+            }).length;
+
+            if (count > bestCount) {
+                best = heading;
+                bestCount = count;
+            }
+        });
+
+        return best;
+    }
+
+    function render(list, root, results) {
+        while (list.firstChild) {
+            list.removeChild(list.firstChild);
+        }
+
+        results.forEach(function(result) {
+            var item = document.createElement('li');
+            var link = document.createElement('a');
+            link.href = root + result.page.url;
+            link.textContent = result.page.title;
+
+            if (result.heading && result.heading.title !== result.page.title) {
+                link.href += '#' + encodeURIComponent(result.heading.id);
+                var heading = document.createElement('span');
+                heading.className = 'rocket-search-heading';
+                heading.textContent = result.heading.title;
+                link.appendChild(document.createTextNode(' › '));
+                link.appendChild(heading);
+            }
+
+            item.appendChild(link);
+            list.appendChild(item);
+        });
+
+        list.hidden = results.length === 0;
+    }
+
+    function setup(container) {
+        var root = container.getAttribute('data-root') || '';
+        var input = container.querySelector('input');
+        var list = container.querySelector('ul');
+
+        input.addEventListener('input', function() {
+            var query = input.value;
+            loadIndex(root).then(function(index) {
+                // Ignore stale responses
+                if (input.value === query) {
+                    render(list, root, search(index, query));
+                }
+            }).catch(function(err) {
+                console.error(err);
+            });
+        });
+    }
+
+    function init() {
+        var containers = document.querySelectorAll('.rocket-search');
+        for (var i = 0; i < containers.length; i += 1) {
+            setup(containers[i]);
+        }
+    }
+
+    if (document.readyState === 'loading') {
+        document.addEventListener('DOMContentLoaded', init);
+    } else {
+        init();
+    }
+})();
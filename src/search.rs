@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use regex::{Captures, Regex};
+use serde_json;
+use page::Page;
+
+pub const INDEX_FILENAME: &str = "searchindex.json";
+pub const SCRIPT_PATH: &str = "_static/search.js";
+const SCRIPT: &str = include_str!("search.js");
+
+// How much more a term counts for in a page's title or headings than in its body
+const TITLE_WEIGHT: u32 = 10;
+const HEADING_WEIGHT: u32 = 5;
+
+lazy_static! {
+    static ref PAT_HEADING: Regex = Regex::new(r#"<h[1-6] id="([^"]*)">(.*?)</h[1-6]>"#)
+        .expect("Failed to compile heading regex");
+    static ref PAT_TAGS: Regex = Regex::new("<[^>]+>").expect("Failed to compile tag regex");
+    static ref PAT_ENTITIES: Regex =
+        Regex::new("&(#?[[:alnum:]]+);").expect("Failed to compile entity regex");
+}
+
+/// Reduce HTML to plain text, replacing each tag with separator and decoding the
+/// entities that Rocket produces.
+fn html_to_text(html: &str, separator: &str) -> String {
+    let text = PAT_TAGS.replace_all(html, separator);
+    PAT_ENTITIES
+        .replace_all(&text, |captures: &Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                _ if entity.starts_with('#') => {
+                    entity[1..].parse::<u32>().ok().and_then(char::from_u32)
+                }
+                _ => None,
+            };
+
+            match decoded {
+                Some(c) => c.to_string(),
+                None => captures[0].to_owned(),
+            }
+        })
+        .into_owned()
+}
+
+/// Split text into lowercase search terms. This must agree with the tokenizer in
+/// search.js.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Heading {
+    pub id: String,
+    pub title: String,
+}
+
+/// The searchable contents of a single page.
+#[derive(Debug, Serialize)]
+pub struct Document {
+    pub slug: String,
+
+    /// The path to this page relative to the output root
+    pub url: String,
+    pub title: String,
+    pub headings: Vec<Heading>,
+
+    #[serde(skip)]
+    terms: HashMap<String, u32>,
+}
+
+impl Document {
+    /// Index a page given its fully linked body.
+    pub fn new(page: &Page, body: &str, pretty_url: bool) -> Self {
+        let title = html_to_text(&page.title(), "").trim().to_owned();
+        let headings: Vec<_> = PAT_HEADING
+            .captures_iter(body)
+            .map(|captures| Heading {
+                id: html_to_text(&captures[1], ""),
+                title: html_to_text(&captures[2], "").trim().to_owned(),
+            })
+            .collect();
+
+        let mut terms = HashMap::new();
+        {
+            let mut add = |text: &str, weight: u32| for term in tokenize(text) {
+                *terms.entry(term).or_insert(0) += weight;
+            };
+
+            add(&title, TITLE_WEIGHT);
+            for heading in &headings {
+                add(&heading.title, HEADING_WEIGHT);
+            }
+            add(&html_to_text(body, " "), 1);
+        }
+
+        Document {
+            slug: page.slug.to_string(),
+            url: page.slug.url(pretty_url),
+            title,
+            headings,
+            terms,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SearchIndex<'a> {
+    pages: &'a [Document],
+
+    /// Maps each term to a list of (page number, score) pairs
+    terms: BTreeMap<&'a str, Vec<(usize, u32)>>,
+}
+
+fn build_index<'a>(documents: &'a [Document]) -> SearchIndex<'a> {
+    let mut terms = BTreeMap::new();
+    for (i, document) in documents.iter().enumerate() {
+        for (term, &score) in &document.terms {
+            terms
+                .entry(term.as_str())
+                .or_insert_with(Vec::new)
+                .push((i, score));
+        }
+    }
+
+    SearchIndex {
+        pages: documents,
+        terms,
+    }
+}
+
+/// Write the search index for the given documents into the output directory, along
+/// with the script that queries it.
+pub fn write(output: &Path, mut documents: Vec<Document>) -> io::Result<()> {
+    documents.sort_by(|a, b| a.slug.cmp(&b.slug));
+    fs::create_dir_all(output)?;
+    let file = File::create(output.join(INDEX_FILENAME))?;
+    serde_json::to_writer(io::BufWriter::new(file), &build_index(&documents))?;
+
+    let script_path = output.join(SCRIPT_PATH);
+    if let Some(parent) = script_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(script_path)?.write_all(SCRIPT.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use page::Slug;
+    use serde_json::Value;
+
+    fn page(slug: &str, title: &str) -> Page {
+        let mut theme_config = serde_json::map::Map::new();
+        theme_config.insert("title".to_owned(), Value::String(title.to_owned()));
+
+        Page {
+            source_path: PathBuf::from(format!("{}.rocket", slug)),
+            slug: Slug::new(slug.to_owned()),
+            body: String::new(),
+            theme_config,
+            assets: vec![],
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Rocket's (:code) directive: 3 ÉCOLES"),
+            vec!["rocket", "code", "directive", "écoles"]
+        );
+
+        assert_eq!(
+            html_to_text("<b>a&#39;s</b> &lt;&amp;&gt; &nbsp;", ""),
+            "a's <&> &nbsp;"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let body = concat!(
+            r#"<section><h1 id="ref-tutorials">Tutorials</h1>"#,
+            r#"<p>Install &amp; run <code>rocket</code>.</p>"#,
+            r#"<section><h2 id="ref-the-lt-code-gt">The <code>build</code> step</h2>"#,
+            r#"<p>Build it.</p></section></section>"#
+        );
+
+        let document = Document::new(&page("tutorials/index", "Tutorials"), body, true);
+        assert_eq!(document.url, "tutorials/index");
+        assert_eq!(document.title, "Tutorials");
+        assert_eq!(
+            document.headings,
+            vec![
+                Heading {
+                    id: "ref-tutorials".to_owned(),
+                    title: "Tutorials".to_owned(),
+                },
+                Heading {
+                    id: "ref-the-lt-code-gt".to_owned(),
+                    title: "The build step".to_owned(),
+                },
+            ]
+        );
+
+        // Title, heading, and body
+        assert_eq!(document.terms["tutorials"], TITLE_WEIGHT + HEADING_WEIGHT + 1);
+        assert_eq!(document.terms["build"], HEADING_WEIGHT + 2);
+        assert_eq!(document.terms["rocket"], 1);
+        assert!(!document.terms.contains_key("amp"));
+        assert!(!document.terms.contains_key("section"));
+    }
+
+    #[test]
+    fn test_index() {
+        let documents = vec![
+            Document::new(&page("a", "Alpha"), "<p>shared alpha</p>", false),
+            Document::new(&page("b", "Beta"), "<p>shared</p>", false),
+        ];
+
+        let index = build_index(&documents);
+        assert_eq!(index.terms["shared"], vec![(0, 1), (1, 1)]);
+        assert_eq!(index.terms["alpha"], vec![(0, TITLE_WEIGHT + 1)]);
+
+        let json = serde_json::to_value(&index).unwrap();
+        assert_eq!(json["pages"][1]["slug"], "b");
+        assert_eq!(json["pages"][1]["url"], "b.html");
+        assert_eq!(json["pages"][1]["title"], "Beta");
+        assert_eq!(json["terms"]["beta"], json!([[1, TITLE_WEIGHT]]));
+        assert!(json["pages"][0].get("terms").is_none());
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use walkdir;
+
+const RELOAD_PATH: &str = "/_rocket/reload";
+const WATCH_INTERVAL_MS: u64 = 500;
+const KEEPALIVE_INTERVAL_MS: u64 = 15_000;
+
+const RELOAD_SCRIPT: &str = concat!(
+    "<script>",
+    r#"new EventSource("/_rocket/reload").onmessage = "#,
+    "function() { window.location.reload(); };",
+    "</script>"
+);
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    match extension.as_ref().map(|e| e.as_ref()) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(n) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                result.push(n);
+                i += 3;
+                continue;
+            }
+        }
+
+        result.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Compute a hash over the names, sizes, and modification times of every file
+/// beneath the given paths.
+fn fingerprint(paths: &[PathBuf]) -> u64 {
+    let mut entries = vec![];
+    for root in paths {
+        for entry in walkdir::WalkDir::new(root) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+
+            if let Ok(metadata) = entry.metadata() {
+                entries.push((entry.path().to_owned(), metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+
+    entries.sort();
+    let mut hasher = DefaultHasher::new();
+    entries.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    include_body: bool,
+) -> io::Result<()> {
+    let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+    for &(key, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    response.push_str("\r\n");
+
+    stream.write_all(response.as_bytes())?;
+    if include_body {
+        stream.write_all(body)?;
+    }
+
+    stream.flush()
+}
+
+fn stream_reload_events(mut stream: TcpStream, generation: &AtomicUsize) -> io::Result<()> {
+    stream.write_all(concat!(
+        "HTTP/1.1 200 OK\r\n",
+        "Content-Type: text/event-stream\r\n",
+        "Cache-Control: no-cache\r\n",
+        "Connection: keep-alive\r\n\r\n"
+    ).as_bytes())?;
+    stream.flush()?;
+
+    let seen = generation.load(Ordering::SeqCst);
+    let mut waited = 0;
+    loop {
+        thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+        if generation.load(Ordering::SeqCst) != seen {
+            stream.write_all(b"data: reload\n\n")?;
+            return stream.flush();
+        }
+
+        // Periodically poke the connection so that we notice closed tabs
+        waited += WATCH_INTERVAL_MS;
+        if waited >= KEEPALIVE_INTERVAL_MS {
+            waited = 0;
+            stream.write_all(b": keepalive\n\n")?;
+            stream.flush()?;
+        }
+    }
+}
+
+/// Map a URL path onto a file beneath root, refusing to escape it.
+fn resolve_path(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_owned();
+    for component in Path::new(url_path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => path.push(c),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+
+    Some(path)
+}
+
+fn handle_connection(mut stream: TcpStream, root: &Path, generation: &AtomicUsize) -> io::Result<()> {
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        reader.read_line(&mut request_line)?;
+
+        // We don't care about any of the headers
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    let include_body = method != "HEAD";
+
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", &[], b"Method not allowed", true);
+    }
+
+    let url_path = percent_decode(target.split(&['?', '#'][..]).next().unwrap_or("/"));
+    if url_path == RELOAD_PATH {
+        return stream_reload_events(stream, generation);
+    }
+
+    let mut path = match resolve_path(root, &url_path) {
+        Some(p) => p,
+        None => return respond(&mut stream, "403 Forbidden", &[], b"Forbidden", include_body),
+    };
+
+    if path.is_dir() {
+        // Relative links assume that directory URLs end with a slash
+        if !url_path.ends_with('/') {
+            let location = format!("{}/", url_path);
+            return respond(
+                &mut stream,
+                "301 Moved Permanently",
+                &[("Location", &location)],
+                b"",
+                include_body,
+            );
+        }
+
+        path.push("index.html");
+    }
+
+    let mut data = vec![];
+    match File::open(&path) {
+        Ok(mut f) => f.read_to_end(&mut data)?,
+        Err(_) => return respond(&mut stream, "404 Not Found", &[], b"Not found", include_body),
+    };
+
+    let content_type = content_type(&path);
+    if content_type.starts_with("text/html") {
+        let html = String::from_utf8_lossy(&data).into_owned();
+        let html = match html.rfind("</body>") {
+            Some(i) => format!("{}{}{}", &html[..i], RELOAD_SCRIPT, &html[i..]),
+            None => html + RELOAD_SCRIPT,
+        };
+        data = html.into_bytes();
+    }
+
+    respond(
+        &mut stream,
+        "200 OK",
+        &[("Content-Type", content_type), ("Cache-Control", "no-cache")],
+        &data,
+        include_body,
+    )
+}
+
+/// Build the project, serve root on localhost, and rebuild whenever anything
+/// under the watched paths changes. Open pages reload after each rebuild.
+pub fn serve<F>(port: u16, root: PathBuf, watch: Vec<PathBuf>, rebuild: F) -> io::Result<()>
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let rebuild = Arc::new(rebuild);
+    let run_build = || {
+        let rebuild = Arc::clone(&rebuild);
+        if thread::spawn(move || rebuild()).join().is_err() {
+            error!("Build failed; waiting for changes");
+        }
+    };
+
+    run_build();
+
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    info!("Serving {} at http://localhost:{}/", root.to_string_lossy(), port);
+
+    let generation = Arc::new(AtomicUsize::new(0));
+    {
+        let generation = Arc::clone(&generation);
+        let root = Arc::new(root);
+        thread::spawn(move || for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(err) => {
+                    warn!("Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+
+            let generation = Arc::clone(&generation);
+            let root = Arc::clone(&root);
+            thread::spawn(move || {
+                if let Err(err) = handle_connection(stream, &root, &generation) {
+                    debug!("Connection closed: {}", err);
+                }
+            });
+        });
+    }
+
+    let mut last_fingerprint = fingerprint(&watch);
+    loop {
+        thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+        let current_fingerprint = fingerprint(&watch);
+        if current_fingerprint == last_fingerprint {
+            continue;
+        }
+
+        info!("Change detected; rebuilding");
+        last_fingerprint = current_fingerprint;
+        run_build();
+        generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode(""), "");
+        assert_eq!(percent_decode("/foo%20bar/"), "/foo bar/");
+        assert_eq!(percent_decode("/caf%C3%A9"), "/café");
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/%zz"), "/%zz");
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let root = Path::new("build");
+        assert_eq!(resolve_path(root, "/"), Some(PathBuf::from("build")));
+        assert_eq!(
+            resolve_path(root, "/reference/./syntax/"),
+            Some(PathBuf::from("build/reference/syntax"))
+        );
+        assert_eq!(resolve_path(root, "/../config.toml"), None);
+        assert_eq!(resolve_path(root, "/reference/../../config.toml"), None);
+    }
+}
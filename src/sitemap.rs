@@ -0,0 +1,133 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use time;
+use page::Slug;
+
+pub const SITEMAP_FILENAME: &str = "sitemap.xml";
+pub const ROBOTS_FILENAME: &str = "robots.txt";
+
+/// A page listed in the sitemap.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub url: String,
+
+    /// When the page's output last changed
+    pub lastmod: Option<SystemTime>,
+}
+
+impl Entry {
+    /// Describe a page that has been written into the output directory. Unchanged
+    /// pages are never rewritten, so the output's modification time is when its
+    /// contents last changed.
+    pub fn new(output: &Path, slug: &Slug, base_url: &str, pretty_url: bool) -> Self {
+        let output_path = slug.create_output_path(output, pretty_url);
+        Entry {
+            url: slug.canonical_url(base_url, pretty_url),
+            lastmod: fs::metadata(output_path)
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Format a timestamp as a W3C datetime, as sitemaps require.
+fn format_lastmod(timestamp: SystemTime) -> Option<String> {
+    let duration = timestamp.duration_since(UNIX_EPOCH).ok()?;
+    let timespec = time::Timespec::new(duration.as_secs() as i64, 0);
+    Some(format!("{}", time::at_utc(timespec).rfc3339()))
+}
+
+fn write_sitemap<W: Write>(out: &mut W, entries: &[Entry]) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )?;
+
+    for entry in entries {
+        writeln!(out, "  <url>")?;
+        writeln!(out, "    <loc>{}</loc>", escape(&entry.url))?;
+        if let Some(lastmod) = entry.lastmod.and_then(format_lastmod) {
+            writeln!(out, "    <lastmod>{}</lastmod>", lastmod)?;
+        }
+        writeln!(out, "  </url>")?;
+    }
+
+    writeln!(out, "</urlset>")
+}
+
+fn write_robots<W: Write>(out: &mut W, base_url: &str) -> io::Result<()> {
+    writeln!(out, "User-agent: *")?;
+    writeln!(out, "Allow: /")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Sitemap: {}/{}",
+        base_url.trim_end_matches('/'),
+        SITEMAP_FILENAME
+    )
+}
+
+/// Write sitemap.xml and a robots.txt pointing to it into the output directory.
+pub fn write(output: &Path, base_url: &str, mut entries: Vec<Entry>) -> io::Result<()> {
+    entries.sort_by(|a, b| a.url.cmp(&b.url));
+    fs::create_dir_all(output)?;
+
+    let mut sitemap = BufWriter::new(File::create(output.join(SITEMAP_FILENAME))?);
+    write_sitemap(&mut sitemap, &entries)?;
+    sitemap.flush()?;
+
+    let mut robots = File::create(output.join(ROBOTS_FILENAME))?;
+    write_robots(&mut robots, base_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sitemap() {
+        let entries = [
+            Entry {
+                url: "https://example.com/a&b/".to_owned(),
+                lastmod: Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000)),
+            },
+            Entry {
+                url: "https://example.com/".to_owned(),
+                lastmod: None,
+            },
+        ];
+
+        let mut sitemap = vec![];
+        write_sitemap(&mut sitemap, &entries).unwrap();
+        let sitemap = String::from_utf8(sitemap).unwrap();
+        assert!(sitemap.contains(concat!(
+            "  <url>\n",
+            "    <loc>https://example.com/a&amp;b/</loc>\n",
+            "    <lastmod>2017-07-14T02:40:00Z</lastmod>\n",
+            "  </url>\n",
+            "  <url>\n",
+            "    <loc>https://example.com/</loc>\n",
+            "  </url>\n",
+            "</urlset>\n"
+        )));
+
+        let mut robots = vec![];
+        write_robots(&mut robots, "https://example.com/").unwrap();
+        assert_eq!(
+            String::from_utf8(robots).unwrap(),
+            "User-agent: *\nAllow: /\n\nSitemap: https://example.com/sitemap.xml\n"
+        );
+    }
+}
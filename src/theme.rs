@@ -1,19 +1,33 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use page::{Page, Slug};
+use directives::AdmonitionConfig;
+use page::{Asset, Page, Slug};
+use search;
 use toctree::TocTree;
 use handlebars::{self, Handlebars};
 use regex::Regex;
 use serde_json;
 use toml;
+use walkdir;
 
 lazy_static! {
     static ref PAT_TAGS: Regex = Regex::new("<[^>]+>").expect("Failed to compile striptags regex");
 }
 
+fn get_current_slug(rc: &handlebars::RenderContext) -> Result<Slug, handlebars::RenderError> {
+    match rc.context().data().get("current_slug") {
+        Some(&serde_json::value::Value::String(ref s)) => Ok(Slug::new(s.to_owned())),
+        _ => Err(handlebars::RenderError::new(
+            "Unable to get current slug while rendering template",
+        )),
+    }
+}
+
 struct TocTreeHelper {
     toctree: Arc<TocTree>,
 }
@@ -27,13 +41,7 @@ impl handlebars::HelperDef for TocTreeHelper {
     ) -> Result<(), handlebars::RenderError> {
         let slug = h.param(0).unwrap().value().as_str().unwrap();
 
-        let current_path = match rc.context().data().get("current_slug") {
-            Some(&serde_json::value::Value::String(ref s)) => Ok(s.to_owned()),
-            _ => Err(handlebars::RenderError::new(
-                "Unable to get current slug while rendering template",
-            )),
-        }?;
-        let current_slug = Slug::new(current_path);
+        let current_slug = get_current_slug(rc)?;
 
         let html = self.toctree
             .generate_html(&Slug::new(slug.to_owned()), &current_slug, true)
@@ -60,16 +68,51 @@ impl handlebars::HelperDef for StripTags {
     }
 }
 
+/// Renders a search box backed by the search index.
+struct SearchHelper {
+    pretty_url: bool,
+}
+
+impl handlebars::HelperDef for SearchHelper {
+    fn call(
+        &self,
+        _: &handlebars::Helper,
+        _: &Handlebars,
+        rc: &mut handlebars::RenderContext,
+    ) -> Result<(), handlebars::RenderError> {
+        let current_slug = get_current_slug(rc)?;
+
+        let html = format!(
+            concat!(
+                r#"<div class="rocket-search" data-root="{}" role="search">"#,
+                r#"<input type="search" placeholder="Search" aria-label="Search" autocomplete="off">"#,
+                r#"<ul class="rocket-search-results" hidden></ul>"#,
+                r#"</div><script src="{}" defer></script>"#
+            ),
+            current_slug.path_to("", self.pretty_url),
+            current_slug.path_to(search::SCRIPT_PATH, self.pretty_url)
+        );
+        rc.writer.write_all(html.as_bytes())?;
+        Ok(())
+    }
+}
+
 #[derive(Deserialize)]
 struct RawConfig {
     constants: Option<serde_json::map::Map<String, serde_json::Value>>,
     templates: HashMap<String, PathBuf>,
+    static_dir: Option<PathBuf>,
+    admonitions: Option<BTreeMap<String, AdmonitionConfig>>,
 }
 
 pub struct Theme {
     path: PathBuf,
     constants: serde_json::map::Map<String, serde_json::Value>,
     templates: HashMap<String, PathBuf>,
+    static_dir: PathBuf,
+
+    /// Admonition styles that the theme provides, which config.toml may override
+    pub admonitions: BTreeMap<String, AdmonitionConfig>,
 }
 
 impl Theme {
@@ -80,23 +123,88 @@ impl Theme {
         let config: RawConfig = toml::from_str(&data).or(Err(()))?;
 
         let constants = config.constants.unwrap_or_else(serde_json::map::Map::new);
+        let theme_dir_path = path.parent().unwrap_or_else(|| Path::new(""));
+        let static_dir = theme_dir_path.join(config.static_dir.unwrap_or_else(|| PathBuf::from("static")));
+
         Ok(Theme {
             path: path.to_owned(),
             constants: constants,
             templates: config.templates,
+            static_dir,
+            admonitions: config.admonitions.unwrap_or_default(),
         })
     }
+
+    /// Every file in the theme's static directory.
+    pub fn get_assets(&self) -> Vec<Asset> {
+        let mut assets = vec![];
+        if !self.static_dir.is_dir() {
+            return assets;
+        }
+
+        for entry in walkdir::WalkDir::new(&self.static_dir) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(err) => {
+                    warn!("Failed to read theme static directory: {}", err);
+                    continue;
+                }
+            };
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            if let Ok(output_path) = entry.path().strip_prefix(&self.static_dir) {
+                assets.push(Asset {
+                    source_path: entry.path().to_owned(),
+                    output_path: output_path.to_owned(),
+                });
+            }
+        }
+
+        assets
+    }
+
+    /// Hash the theme configuration and the contents of every template it uses.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let theme_dir_path = self.path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut paths = vec![self.path.to_owned()];
+        let mut templates: Vec<_> = self.templates.iter().collect();
+        templates.sort();
+        for (template_name, template_path) in templates {
+            template_name.hash(&mut hasher);
+            paths.push(theme_dir_path.join(template_path));
+        }
+
+        for path in paths {
+            let mut data = vec![];
+            if let Ok(mut file) = File::open(&path) {
+                let _ = file.read_to_end(&mut data);
+            }
+            data.hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
 }
 
 pub struct Renderer {
     handlebars: Handlebars,
     constants: serde_json::map::Map<String, serde_json::Value>,
+    toctree: Arc<TocTree>,
+    pretty_url: bool,
+    base_url: Option<String>,
 }
 
 impl Renderer {
     pub fn new(
         theme: Theme,
         toctree: Arc<TocTree>,
+        pretty_url: bool,
+        base_url: Option<String>,
     ) -> Result<Renderer, handlebars::TemplateFileError> {
         let mut handlebars = Handlebars::new();
         let theme_dir_path = theme.path.parent().unwrap_or_else(|| Path::new(""));
@@ -112,10 +220,22 @@ impl Renderer {
 
         handlebars.register_helper("striptags", Box::new(StripTags));
         handlebars.register_helper("toctree", Box::new(helper));
+        handlebars.register_helper("search", Box::new(SearchHelper { pretty_url }));
 
         Ok(Renderer {
             handlebars,
             constants: theme.constants,
+            toctree,
+            pretty_url,
+            base_url,
+        })
+    }
+
+    /// Describe a link from the current page to target for use in templates.
+    fn link(&self, current_slug: &Slug, target: &Slug) -> serde_json::Value {
+        json!({
+            "title": self.toctree.get_title(target).unwrap_or("Untitled"),
+            "url": current_slug.path_to(&target.url(self.pretty_url), self.pretty_url),
         })
     }
 
@@ -126,12 +246,23 @@ impl Renderer {
         page: &Page,
         body: &str,
     ) -> Result<String, handlebars::RenderError> {
+        let slug = &page.slug;
+        let link = |target: &Slug| self.link(slug, target);
+        let canonical_url = self.base_url
+            .as_ref()
+            .map(|base_url| slug.canonical_url(base_url, self.pretty_url));
+
         let ctx = json!({
-            "current_slug": serde_json::value::Value::String(page.slug.as_ref().to_owned()),
+            "current_slug": serde_json::value::Value::String(slug.as_ref().to_owned()),
             "page": &page.theme_config,
             "project": project_args,
             "theme": self.constants,
             "body": body,
+            "canonical_url": canonical_url,
+            "prev": self.toctree.previous(slug).map(&link),
+            "next": self.toctree.next(slug).map(&link),
+            "parents": self.toctree.parents(slug).into_iter().map(&link).collect::<Vec<_>>(),
+            "children": self.toctree.children(slug).into_iter().map(&link).collect::<Vec<_>>(),
         });
 
         self.handlebars.render(template_name, &ctx)
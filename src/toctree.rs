@@ -1,11 +1,101 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
-use page::Slug;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use diagnostic::{Diagnostic, ErrorCode, Reporter};
+use lex::Span;
+use page::{Page, Slug};
+
+/// Where a toctree entry was written, so that problems with it can be reported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntrySource {
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+impl EntrySource {
+    fn describe(&self) -> String {
+        format!("{}:{}", self.path.display(), self.span.lineno() + 1)
+    }
+}
 
 #[derive(Debug)]
 struct TocTreeElement {
     slug: Slug,
     title: Option<String>,
+    source: EntrySource,
+}
+
+impl Hash for TocTreeElement {
+    // Moving a toctree directive around its file shouldn't invalidate every page
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.slug.hash(state);
+        self.title.hash(state);
+    }
+}
+
+/// The state of a depth-first walk over the tree.
+#[derive(Default)]
+struct Walk<'a> {
+    /// Whether the pages being visited are reachable from the root
+    reachable: bool,
+
+    order: Vec<&'a Slug>,
+    primary_parents: HashMap<&'a Slug, &'a Slug>,
+
+    /// Maps each visited page to the first entry that listed it
+    first_entries: HashMap<&'a Slug, &'a TocTreeElement>,
+    visited: HashSet<&'a Slug>,
+
+    /// The chain of pages leading to the page being visited
+    ancestors: Vec<&'a Slug>,
+
+    /// Entries listing a page that was already visited
+    duplicates: Vec<&'a TocTreeElement>,
+
+    /// Entries listing one of their own ancestors, along with the listing page
+    cycles: Vec<(&'a Slug, &'a TocTreeElement)>,
+}
+
+impl<'a> Walk<'a> {
+    fn visit(
+        &mut self,
+        children: &'a HashMap<Slug, Vec<TocTreeElement>>,
+        pages: &HashMap<&Slug, &Page>,
+        slug: &'a Slug,
+    ) {
+        self.visited.insert(slug);
+        if self.reachable {
+            self.order.push(slug);
+        }
+
+        let entries = match children.get(slug) {
+            Some(entries) => entries,
+            None => return,
+        };
+
+        self.ancestors.push(slug);
+        for entry in entries {
+            // Entries without a page are reported separately
+            if !pages.contains_key(&entry.slug) {
+                continue;
+            }
+
+            if self.ancestors.contains(&&entry.slug) {
+                self.cycles.push((slug, entry));
+            } else if self.visited.contains(&entry.slug) {
+                self.duplicates.push(entry);
+            } else {
+                self.first_entries.insert(&entry.slug, entry);
+                if self.reachable {
+                    self.primary_parents.insert(&entry.slug, slug);
+                }
+
+                self.visit(children, pages, &entry.slug);
+            }
+        }
+        self.ancestors.pop();
+    }
 }
 
 pub struct TocTree {
@@ -19,6 +109,21 @@ pub struct TocTree {
 
     titles: HashMap<Slug, String>,
     pretty_url: bool,
+
+    /// Every page reachable from the root, in reading order
+    order: Vec<Slug>,
+
+    /// Maps slug -> index into order
+    positions: HashMap<Slug, usize>,
+
+    /// Maps child -> the parent through which it is reached in reading order
+    primary_parents: HashMap<Slug, Slug>,
+
+    /// (parent, child) entries that would lead back to one of the parent's ancestors
+    cyclic_entries: HashSet<(Slug, Slug)>,
+
+    /// Pages that the build's tags leave out
+    excluded: HashSet<Slug>,
 }
 
 impl TocTree {
@@ -29,6 +134,11 @@ impl TocTree {
             inverse_children: HashMap::new(),
             titles: HashMap::new(),
             pretty_url: pretty_url,
+            order: vec![],
+            positions: HashMap::new(),
+            primary_parents: HashMap::new(),
+            cyclic_entries: HashSet::new(),
+            excluded: HashSet::new(),
         }
     }
 
@@ -36,10 +146,17 @@ impl TocTree {
         Self::new(Slug::new("".to_owned()), false)
     }
 
-    pub fn add(&mut self, parent_slug: &Slug, child: Slug, title: Option<String>) {
+    pub fn add(
+        &mut self,
+        parent_slug: &Slug,
+        child: Slug,
+        title: Option<String>,
+        source: EntrySource,
+    ) {
         let new_element = TocTreeElement {
             slug: child.to_owned(),
             title: title,
+            source,
         };
 
         self.inverse_children
@@ -52,8 +169,210 @@ impl TocTree {
             .push(new_element);
     }
 
-    pub fn finish(&mut self, titles: &HashMap<Slug, String>) {
-        self.titles = titles.clone();
+    /// Remove an excluded page's own entries. Entries listing it are left out of
+    /// navigation rather than reported as missing.
+    pub fn exclude(&mut self, slug: &Slug) {
+        for child in self.children.remove(slug).unwrap_or_default() {
+            if let Some(parents) = self.inverse_children.get_mut(&child.slug) {
+                parents.retain(|parent| parent != slug);
+            }
+        }
+
+        self.excluded.insert(slug.to_owned());
+    }
+
+    /// Prepare the tree for rendering once every page has been compiled, and report
+    /// any problems with its structure.
+    pub fn finish<'a, I>(&mut self, pages: I, reporter: &Reporter)
+    where
+        I: Iterator<Item = &'a Page>,
+    {
+        let pages: HashMap<&Slug, &Page> = pages.map(|page| (&page.slug, page)).collect();
+        self.titles = pages
+            .iter()
+            .map(|(&slug, page)| (slug.to_owned(), page.title()))
+            .collect();
+
+        let (order, primary_parents, cyclic_entries) = {
+            let walk = self.walk(&pages);
+            self.validate(&pages, &walk, reporter);
+
+            let cyclic_entries: HashSet<_> = walk.cycles
+                .iter()
+                .map(|&(parent, entry)| (parent.to_owned(), entry.slug.to_owned()))
+                .collect();
+            let primary_parents: HashMap<_, _> = walk.primary_parents
+                .iter()
+                .map(|(&child, &parent)| (child.to_owned(), parent.to_owned()))
+                .collect();
+            let order: Vec<_> = walk.order.into_iter().cloned().collect();
+            (order, primary_parents, cyclic_entries)
+        };
+
+        self.positions = order
+            .iter()
+            .enumerate()
+            .map(|(i, slug)| (slug.to_owned(), i))
+            .collect();
+        self.order = order;
+        self.primary_parents = primary_parents;
+        self.cyclic_entries = cyclic_entries;
+    }
+
+    /// Walk the tree depth-first from the root to find the reading order of every
+    /// page. A page listed by more than one toctree is placed at its first appearance.
+    ///
+    /// The parts of the tree that the root doesn't reach are walked afterwards so that
+    /// any problems within them are still found.
+    fn walk<'a>(&'a self, pages: &HashMap<&Slug, &Page>) -> Walk<'a> {
+        let mut walk = Walk {
+            reachable: true,
+            ..Walk::default()
+        };
+        walk.visit(&self.children, pages, &self.root);
+        walk.reachable = false;
+
+        // Start from pages that no toctree lists, then from whatever loops remain
+        let mut parents: Vec<_> = self.children.keys().collect();
+        parents.sort_by_key(|&slug| (self.inverse_children.contains_key(slug), slug.as_ref()));
+        for parent in parents {
+            if !walk.visited.contains(parent) {
+                walk.visit(&self.children, pages, parent);
+            }
+        }
+
+        walk
+    }
+
+    fn validate(&self, pages: &HashMap<&Slug, &Page>, walk: &Walk, reporter: &Reporter) {
+        let report = |diagnostic: Diagnostic, source: &EntrySource| {
+            reporter.report(&diagnostic.at_path_span(source.span), Some(&source.path));
+        };
+
+        let mut parents: Vec<_> = self.children.iter().collect();
+        parents.sort_by_key(|&(slug, _)| slug.as_ref());
+
+        for (_, children) in parents {
+            let missing = children.iter().filter(|child| {
+                !pages.contains_key(&child.slug) && !self.excluded.contains(&child.slug)
+            });
+
+            for child in missing {
+                let diagnostic = Diagnostic::error(
+                    ErrorCode::MissingPage,
+                    format!("toctree entry '{}' has no page", child.slug),
+                );
+                report(diagnostic, &child.source);
+            }
+        }
+
+        for &(parent, entry) in &walk.cycles {
+            let diagnostic = Diagnostic::error(
+                ErrorCode::TocTreeCycle,
+                format!("toctree entry '{}' creates a cycle", entry.slug),
+            ).with_note(format!("'{}' is already an ancestor of '{}'", entry.slug, parent));
+            report(diagnostic, &entry.source);
+        }
+
+        for entry in &walk.duplicates {
+            let mut diagnostic = Diagnostic::warning(
+                ErrorCode::DuplicateEntry,
+                format!("page '{}' is listed by more than one toctree entry", entry.slug),
+            );
+
+            if let Some(first) = walk.first_entries.get(&entry.slug) {
+                diagnostic = diagnostic
+                    .with_note(format!("first listed at {}", first.source.describe()));
+            }
+            report(diagnostic, &entry.source);
+        }
+
+        // Without a root page, nothing is reachable
+        if !pages.contains_key(&self.root) {
+            return;
+        }
+
+        let reachable: HashSet<_> = walk.order.iter().collect();
+        let mut orphans: Vec<_> = pages
+            .values()
+            .filter(|page| !reachable.contains(&&page.slug))
+            .collect();
+        orphans.sort_by_key(|page| page.slug.as_ref());
+
+        for page in orphans {
+            let diagnostic = Diagnostic::warning(
+                ErrorCode::UnreachablePage,
+                format!("page '{}' is not included in any toctree", page.slug),
+            ).with_note(format!("add it to a toctree reachable from '{}'", self.root));
+            reporter.report(&diagnostic, Some(&page.source_path));
+        }
+    }
+
+    /// The page before slug in reading order.
+    pub fn previous(&self, slug: &Slug) -> Option<&Slug> {
+        match self.positions.get(slug) {
+            Some(&i) if i > 0 => self.order.get(i - 1),
+            _ => None,
+        }
+    }
+
+    /// The page after slug in reading order.
+    pub fn next(&self, slug: &Slug) -> Option<&Slug> {
+        self.positions
+            .get(slug)
+            .and_then(|&i| self.order.get(i + 1))
+    }
+
+    /// The chain of pages leading from the root to slug, not including slug itself.
+    pub fn parents(&self, slug: &Slug) -> Vec<&Slug> {
+        let mut parents = vec![];
+        let mut current = slug;
+        while let Some(parent) = self.primary_parents.get(current) {
+            parents.push(parent);
+            current = parent;
+        }
+
+        parents.reverse();
+        parents
+    }
+
+    /// Every page in reading order, along with the number of the chapter that it
+    /// belongs to. Each page listed by the root's toctree starts a chapter, numbered
+    /// from 1; the root itself is in no chapter.
+    pub fn chapters(&self) -> Vec<(&Slug, Option<usize>)> {
+        let mut chapter = None;
+        self.order
+            .iter()
+            .map(|slug| {
+                if self.primary_parents.get(slug) == Some(&self.root) {
+                    chapter = Some(chapter.unwrap_or(0) + 1);
+                }
+
+                (slug, chapter)
+            })
+            .collect()
+    }
+
+    /// The pages listed by slug's toctree.
+    pub fn children(&self, slug: &Slug) -> Vec<&Slug> {
+        match self.children.get(slug) {
+            Some(children) => children.iter().map(|child| &child.slug).collect(),
+            None => vec![],
+        }
+    }
+
+    /// The title of a page. A title given by the toctree entry through which the page
+    /// is reached takes precedence over the page's own title.
+    pub fn get_title(&self, slug: &Slug) -> Option<&str> {
+        let entry_title = self.primary_parents
+            .get(slug)
+            .and_then(|parent| self.children.get(parent))
+            .and_then(|children| children.iter().find(|child| &child.slug == slug))
+            .and_then(|child| child.title.as_ref());
+
+        entry_title
+            .or_else(|| self.titles.get(slug))
+            .map(|title| title.as_str())
     }
 
     pub fn generate_html(
@@ -79,13 +398,22 @@ impl TocTree {
                 .ok_or_else(|| format!("Failed to find toctree root '{}'", &self.root))?;
             result.push(Cow::Owned(format!(
                 r#"<a href="{}">{}</a>"#,
-                current_slug.path_to("", self.pretty_url),
+                current_slug.path_to(&self.root.url(self.pretty_url), self.pretty_url),
                 title
             )));
             result.push(Cow::Borrowed("</li>"));
         }
 
         for child in children {
+            // Skip excluded pages, and problems that finish() reports: entries
+            // without a page, and entries that lead around a cycle
+            if !self.titles.contains_key(&child.slug)
+                || self.cyclic_entries
+                    .contains(&(root.to_owned(), child.slug.to_owned()))
+            {
+                continue;
+            }
+
             if self.is_ancestor_of(&child.slug, current_slug) {
                 result.push(Cow::Borrowed(r#"<li class="current">"#));
             } else {
@@ -101,7 +429,7 @@ impl TocTree {
 
             result.push(Cow::Owned(format!(
                 r#"<a href="{}">{}</a>"#,
-                current_slug.path_to(child.slug.as_ref(), self.pretty_url),
+                current_slug.path_to(&child.slug.url(self.pretty_url), self.pretty_url),
                 title
             )));
             result.extend(self.generate_html(&child.slug, current_slug, false)?);
@@ -114,15 +442,230 @@ impl TocTree {
 
     /// Return True if ancestor is a parent/grand-parent/... of child.
     fn is_ancestor_of(&self, ancestor: &Slug, child: &Slug) -> bool {
-        if ancestor == child {
-            return true;
+        let mut visited = HashSet::new();
+        let mut stack = vec![child];
+        while let Some(slug) = stack.pop() {
+            if slug == ancestor {
+                return true;
+            }
+
+            // Guard against toctree cycles
+            if !visited.insert(slug) {
+                continue;
+            }
+
+            if let Some(parents) = self.inverse_children.get(slug) {
+                stack.extend(parents);
+            }
         }
 
-        match self.inverse_children.get(child) {
-            Some(parents) => parents
-                .iter()
-                .any(|parent| self.is_ancestor_of(ancestor, parent)),
-            _ => false,
+        false
+    }
+}
+
+impl Hash for TocTree {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.root.hash(state);
+        self.pretty_url.hash(state);
+
+        let mut children: Vec<_> = self.children.iter().collect();
+        children.sort_by_key(|&(slug, _)| slug.as_ref());
+        children.hash(state);
+
+        let mut titles: Vec<_> = self.titles.iter().collect();
+        titles.sort_by_key(|&(slug, _)| slug.as_ref());
+        titles.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use serde_json;
+    use diagnostic::Counts;
+    use lex::Position;
+
+    fn slug(s: &str) -> Slug {
+        Slug::new(s.to_owned())
+    }
+
+    fn page(slug_str: &str, title: &str) -> Page {
+        let mut theme_config = serde_json::map::Map::new();
+        theme_config.insert("title".to_owned(), serde_json::Value::String(title.to_owned()));
+
+        Page {
+            source_path: PathBuf::from(format!("content/{}.rocket", slug_str)),
+            slug: slug(slug_str),
+            body: String::new(),
+            theme_config,
+            assets: vec![],
         }
     }
+
+    /// Add an entry as though it were written on the first line of parent's source.
+    fn add(toctree: &mut TocTree, parent: &str, child: &str, title: Option<&str>) {
+        let position = Position::new(0, 0, 0);
+        let source = EntrySource {
+            path: PathBuf::from(format!("content/{}.rocket", parent)),
+            span: Span::new(position, position),
+        };
+
+        toctree.add(&slug(parent), slug(child), title.map(str::to_owned), source);
+    }
+
+    fn toctree_with_reporter(reporter: &Reporter) -> TocTree {
+        let mut toctree = TocTree::new(slug("index"), true);
+        add(&mut toctree, "index", "tutorials", None);
+        add(&mut toctree, "index", "reference", Some("API"));
+        add(&mut toctree, "tutorials", "tutorials/install", None);
+        add(&mut toctree, "tutorials", "tutorials/first", None);
+
+        // A page listed twice, and a cycle back to the root
+        add(&mut toctree, "reference", "tutorials/install", None);
+        add(&mut toctree, "reference", "index", None);
+
+        let pages = [
+            page("index", "Home"),
+            page("tutorials", "Tutorials"),
+            page("tutorials/install", "Installing"),
+            page("tutorials/first", "First Steps"),
+            page("reference", "Reference"),
+        ];
+        toctree.finish(pages.iter(), reporter);
+        toctree
+    }
+
+    fn toctree() -> TocTree {
+        toctree_with_reporter(&Reporter::default())
+    }
+
+    #[test]
+    fn test_order() {
+        let toctree = toctree();
+        assert_eq!(
+            toctree.order,
+            vec![
+                slug("index"),
+                slug("tutorials"),
+                slug("tutorials/install"),
+                slug("tutorials/first"),
+                slug("reference"),
+            ]
+        );
+
+        assert_eq!(toctree.previous(&slug("index")), None);
+        assert_eq!(toctree.next(&slug("index")), Some(&slug("tutorials")));
+        assert_eq!(toctree.previous(&slug("reference")), Some(&slug("tutorials/first")));
+        assert_eq!(toctree.next(&slug("reference")), None);
+        assert_eq!(toctree.next(&slug("orphan")), None);
+    }
+
+    #[test]
+    fn test_relations() {
+        let toctree = toctree();
+        assert_eq!(
+            toctree.parents(&slug("tutorials/install")),
+            vec![&slug("index"), &slug("tutorials")]
+        );
+        assert_eq!(toctree.parents(&slug("index")), Vec::<&Slug>::new());
+        assert_eq!(
+            toctree.children(&slug("tutorials")),
+            vec![&slug("tutorials/install"), &slug("tutorials/first")]
+        );
+        assert_eq!(
+            toctree.chapters(),
+            vec![
+                (&slug("index"), None),
+                (&slug("tutorials"), Some(1)),
+                (&slug("tutorials/install"), Some(1)),
+                (&slug("tutorials/first"), Some(1)),
+                (&slug("reference"), Some(2)),
+            ]
+        );
+
+        assert_eq!(toctree.get_title(&slug("index")), Some("Home"));
+        assert_eq!(toctree.get_title(&slug("reference")), Some("API"));
+        assert_eq!(toctree.get_title(&slug("tutorials/first")), Some("First Steps"));
+        assert_eq!(toctree.get_title(&slug("orphan")), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut toctree = TocTree::new(slug("index"), true);
+        add(&mut toctree, "index", "a", None);
+        add(&mut toctree, "index", "missing", None);
+        add(&mut toctree, "orphan", "orphan/child", None);
+
+        let pages = [
+            page("index", "Home"),
+            page("a", "A"),
+            page("orphan", "Orphan"),
+            page("orphan/child", "Child"),
+        ];
+
+        let reporter = Reporter::default();
+        toctree.finish(pages.iter(), &reporter);
+        assert_eq!(reporter.counts(), Counts { errors: 1, warnings: 2 });
+
+        let html = toctree
+            .generate_html(&slug("index"), &slug("a"), false)
+            .unwrap()
+            .concat();
+        assert!(!html.contains("missing"));
+    }
+
+    #[test]
+    fn test_structure() {
+        let reporter = Reporter::default();
+        let toctree = toctree_with_reporter(&reporter);
+        assert_eq!(reporter.counts(), Counts { errors: 1, warnings: 1 });
+        assert_eq!(
+            toctree.cyclic_entries,
+            [(slug("reference"), slug("index"))].iter().cloned().collect()
+        );
+
+        // Rendering must not follow the cycle
+        assert!(toctree.is_ancestor_of(&slug("reference"), &slug("tutorials")));
+        assert!(!toctree.is_ancestor_of(&slug("orphan"), &slug("tutorials")));
+        let html = toctree
+            .generate_html(&slug("index"), &slug("reference"), true)
+            .unwrap()
+            .concat();
+        assert_eq!(html.matches("Installing").count(), 2);
+
+        // A loop that the root doesn't reach
+        let mut toctree = TocTree::new(slug("index"), true);
+        add(&mut toctree, "a", "b", None);
+        add(&mut toctree, "b", "a", None);
+        add(&mut toctree, "b", "b", None);
+
+        let reporter = Reporter::default();
+        let pages = [page("index", "Home"), page("a", "A"), page("b", "B")];
+        toctree.finish(pages.iter(), &reporter);
+        assert_eq!(reporter.counts(), Counts { errors: 2, warnings: 2 });
+        assert!(!toctree.is_ancestor_of(&slug("index"), &slug("a")));
+    }
+
+    #[test]
+    fn test_plain_urls() {
+        let mut toctree = TocTree::new(slug("index"), false);
+        add(&mut toctree, "index", "tutorials", None);
+        add(&mut toctree, "tutorials", "tutorials/install", None);
+
+        let pages = [
+            page("index", "Home"),
+            page("tutorials", "Tutorials"),
+            page("tutorials/install", "Installing"),
+        ];
+        toctree.finish(pages.iter(), &Reporter::default());
+
+        let html = toctree
+            .generate_html(&slug("index"), &slug("tutorials/install"), true)
+            .unwrap()
+            .concat();
+        assert!(html.contains(r#"<a href="../index.html">Home</a>"#));
+        assert!(html.contains(r#"<a href="../tutorials.html">Tutorials</a>"#));
+        assert!(html.contains(r#"<a href="../tutorials/install.html">Installing</a>"#));
+    }
 }